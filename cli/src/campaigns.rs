@@ -0,0 +1,135 @@
+use anyhow::{anyhow, Result};
+use aws_sdk_dynamodb::types::AttributeValue;
+use clap::Subcommand;
+use shared::campaign::{Campaign, CampaignStatus};
+use shared::unix_now;
+
+use crate::{format_timestamp, Context};
+
+#[derive(Debug, Subcommand)]
+pub enum CampaignCommand {
+    /// Create a campaign
+    Create {
+        campaign_id: String,
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        thank_you_message: String,
+        /// The key of the reward file in the resources bucket
+        #[arg(long, default_value = "")]
+        reward_s3_key: String,
+        /// The key of the confirmation email template in the resources bucket
+        #[arg(long, default_value = "")]
+        email_template_s3_key: String,
+        /// The name the emails are sent from
+        #[arg(long)]
+        from_name: Option<String>,
+        /// Where the replies to the emails go
+        #[arg(long)]
+        reply_to: Option<String>,
+        /// The locale of the texts of the campaign (en by default)
+        #[arg(long)]
+        locale: Option<String>,
+    },
+    /// List the campaigns
+    List {
+        /// Include the archived campaigns
+        #[arg(long)]
+        all: bool,
+    },
+    /// Show a campaign as JSON
+    Show { campaign_id: String },
+}
+
+pub async fn run(command: CampaignCommand, context: &Context) -> Result<()> {
+    match command {
+        CampaignCommand::Create {
+            campaign_id,
+            name,
+            thank_you_message,
+            reward_s3_key,
+            email_template_s3_key,
+            from_name,
+            reply_to,
+            locale,
+        } => {
+            let now = unix_now();
+            let campaign = Campaign {
+                campaign_id,
+                name,
+                reward_s3_key,
+                email_template_s3_key,
+                thank_you_message,
+                sender_email: None,
+                from_name,
+                reply_to,
+                locale,
+                translations: Default::default(),
+                fields: vec![],
+                drip: vec![],
+                status: CampaignStatus::Active,
+                created_at: Some(now),
+                updated_at: Some(now),
+            };
+            campaign.validate()?;
+
+            context
+                .dynamodb_client
+                .put_item()
+                .table_name(&context.campaigns_table)
+                .set_item(Some(serde_dynamo::to_item(&campaign)?))
+                .condition_expression("attribute_not_exists(campaign_id)")
+                .send()
+                .await
+                .map_err(|err| match err.as_service_error() {
+                    Some(e) if e.is_conditional_check_failed_exception() => {
+                        anyhow!("Campaign {} already exists", campaign.campaign_id)
+                    }
+                    _ => err.into(),
+                })?;
+            println!("{}", serde_json::to_string_pretty(&campaign)?);
+        }
+        CampaignCommand::List { all } => {
+            let items = context
+                .dynamodb_client
+                .scan()
+                .table_name(&context.campaigns_table)
+                .into_paginator()
+                .items()
+                .send()
+                .collect::<Result<Vec<_>, _>>()
+                .await?;
+            let mut campaigns: Vec<Campaign> = serde_dynamo::from_items(items)?;
+            campaigns.retain(|c| all || c.is_active());
+            campaigns.sort_by(|a, b| a.campaign_id.cmp(&b.campaign_id));
+
+            println!("{:<24} {:<10} {:<22} NAME", "CAMPAIGN", "STATUS", "CREATED");
+            for campaign in campaigns {
+                println!(
+                    "{:<24} {:<10} {:<22} {}",
+                    campaign.campaign_id,
+                    serde_json::to_value(campaign.status)?
+                        .as_str()
+                        .unwrap_or_default(),
+                    format_timestamp(campaign.created_at),
+                    campaign.name
+                );
+            }
+        }
+        CampaignCommand::Show { campaign_id } => {
+            let item = context
+                .dynamodb_client
+                .get_item()
+                .table_name(&context.campaigns_table)
+                .key("campaign_id", AttributeValue::S(campaign_id.clone()))
+                .send()
+                .await?
+                .item
+                .ok_or_else(|| anyhow!("Campaign {} not found", campaign_id))?;
+            let campaign: Campaign = serde_dynamo::from_item(item)?;
+            println!("{}", serde_json::to_string_pretty(&campaign)?);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+mod campaigns;
+mod subscribers;
+mod token;
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+
+/// Operate a tinykit deployment.
+#[derive(Debug, Parser)]
+#[command(name = "tinykit")]
+struct Cli {
+    /// The AppId the stack was deployed with, used to name its tables and queue
+    #[arg(long, env = "TINYKIT_APP_ID", global = true)]
+    app_id: Option<String>,
+    /// Send the DynamoDB requests to this endpoint (e.g. http://localhost:8000
+    /// for DynamoDB Local)
+    #[arg(long, env = "TINYKIT_ENDPOINT_URL", global = true)]
+    endpoint_url: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Manage campaigns
+    #[command(subcommand)]
+    Campaign(campaigns::CampaignCommand),
+    /// Inspect and manage the subscribers of a campaign
+    #[command(subcommand)]
+    Subscribers(subscribers::SubscribersCommand),
+    /// Queue the confirmation email of a pending subscription again
+    Resend {
+        #[arg(long)]
+        campaign: String,
+        subscription_id: String,
+        /// Resend even if the subscription is already confirmed
+        #[arg(long)]
+        force: bool,
+    },
+    /// Inspect confirmation tokens
+    #[command(subcommand)]
+    Token(token::TokenCommand),
+}
+
+/// The clients and resource names of a deployment, named like the template
+/// names them.
+pub struct Context {
+    pub dynamodb_client: aws_sdk_dynamodb::Client,
+    pub sqs_client: aws_sdk_sqs::Client,
+    pub s3_client: aws_sdk_s3::Client,
+    pub lambda_client: aws_sdk_lambda::Client,
+    pub campaigns_table: String,
+    pub subscriptions_table: String,
+    pub email_queue_name: String,
+    pub resources_bucket: String,
+    pub import_function: String,
+}
+
+impl Context {
+    async fn new(app_id: Option<&str>, endpoint_url: Option<&str>) -> Result<Self> {
+        let app_id = app_id.ok_or_else(|| anyhow!("--app-id (or TINYKIT_APP_ID) is required"))?;
+        let sdk_config = aws_config::load_from_env().await;
+
+        let mut dynamodb_config = aws_sdk_dynamodb::config::Builder::from(&sdk_config);
+        if let Some(endpoint_url) = endpoint_url {
+            dynamodb_config = dynamodb_config.endpoint_url(endpoint_url);
+        }
+
+        Ok(Self {
+            dynamodb_client: aws_sdk_dynamodb::Client::from_conf(dynamodb_config.build()),
+            sqs_client: aws_sdk_sqs::Client::new(&sdk_config),
+            s3_client: aws_sdk_s3::Client::new(&sdk_config),
+            lambda_client: aws_sdk_lambda::Client::new(&sdk_config),
+            campaigns_table: format!("tinykit-{}-campaigns", app_id),
+            subscriptions_table: format!("tinykit-{}-subscriptions", app_id),
+            email_queue_name: format!("tinykit-{}-email", app_id),
+            resources_bucket: format!("tinykit-{}-rewards", app_id),
+            import_function: format!("tinykit-{}-import-subscribers", app_id),
+        })
+    }
+
+    pub async fn email_queue_url(&self) -> Result<String> {
+        self.sqs_client
+            .get_queue_url()
+            .queue_name(&self.email_queue_name)
+            .send()
+            .await?
+            .queue_url
+            .ok_or_else(|| anyhow!("Queue {} not found", self.email_queue_name))
+    }
+}
+
+/// Formats a unix timestamp for humans.
+pub fn format_timestamp(timestamp: Option<u64>) -> String {
+    match timestamp {
+        Some(timestamp) => shared::export::format_timestamp(timestamp),
+        None => "-".to_string(),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let context = || Context::new(cli.app_id.as_deref(), cli.endpoint_url.as_deref());
+
+    match cli.command {
+        Command::Campaign(command) => campaigns::run(command, &context().await?).await,
+        Command::Subscribers(command) => subscribers::run(command, &context().await?).await,
+        Command::Resend {
+            ref campaign,
+            ref subscription_id,
+            force,
+        } => subscribers::resend(campaign, subscription_id, force, &context().await?).await,
+        // decoding a token needs AWS only to load the keyring from SSM
+        Command::Token(command) => token::run(command).await,
+    }
+}
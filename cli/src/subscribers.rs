@@ -0,0 +1,325 @@
+use anyhow::{anyhow, Result};
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_lambda::primitives::Blob;
+use aws_sdk_lambda::types::InvocationType;
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::ByteStream;
+use clap::Subcommand;
+use serde::de::DeserializeOwned;
+use shared::export::{export_to_s3, export_to_writer, parse_timestamp, ExportFilter, ExportFormat};
+use shared::import::{import_prefix, ImportJob, ImportMode};
+use shared::subscription::{Subscription, SubscriptionStatus};
+use shared::{unix_now, SubscribeEventPayload};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::{format_timestamp, Context};
+
+#[derive(Debug, Subcommand)]
+pub enum SubscribersCommand {
+    /// List the subscribers of a campaign
+    List {
+        #[arg(long)]
+        campaign: String,
+    },
+    /// Export the subscribers of a campaign to the resources bucket, printing
+    /// a presigned download link
+    Export {
+        #[arg(long)]
+        campaign: String,
+        /// csv or jsonl
+        #[arg(long, value_parser = parse_name::<ExportFormat>, default_value = "csv")]
+        format: ExportFormat,
+        /// pending, confirmed or unsubscribed
+        #[arg(long, value_parser = parse_name::<SubscriptionStatus>)]
+        status: Option<SubscriptionStatus>,
+        /// Only the subscriptions created from this date (e.g. 2024-06-01)
+        #[arg(long, value_parser = parse_timestamp)]
+        from: Option<u64>,
+        /// Only the subscriptions created before this date
+        #[arg(long, value_parser = parse_timestamp)]
+        to: Option<u64>,
+        /// Write to this file (or `-` for the standard output) instead
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// How long the download link is valid for, in seconds
+        #[arg(long, default_value_t = 3600)]
+        expires_in: u64,
+    },
+    /// Import subscribers from a CSV file with an `email` column (and an
+    /// optional `confirmed_at` one), in the background
+    Import {
+        #[arg(long)]
+        campaign: String,
+        /// A local file, uploaded to the resources bucket first
+        #[arg(long, conflicts_with = "s3_key", required_unless_present = "s3_key")]
+        file: Option<PathBuf>,
+        /// A file already in the resources bucket
+        #[arg(long)]
+        s3_key: Option<String>,
+        /// pre_confirmed (they already opted in elsewhere) or confirm (send
+        /// them a confirmation email)
+        #[arg(long, value_parser = parse_name::<ImportMode>)]
+        mode: ImportMode,
+        /// Where the subscribers come from (e.g. mailchimp)
+        #[arg(long)]
+        source: Option<String>,
+    },
+    /// Delete subscriptions (e.g. to honour a data deletion request)
+    Delete {
+        #[arg(long)]
+        campaign: String,
+        #[arg(required = true)]
+        subscription_ids: Vec<String>,
+    },
+}
+
+/// Parses the snake_case name of a shared enum.
+fn parse_name<T: DeserializeOwned>(value: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| format!("invalid value {}", value))
+}
+
+async fn campaign_subscriptions(campaign_id: &str, context: &Context) -> Result<Vec<Subscription>> {
+    let items = context
+        .dynamodb_client
+        .query()
+        .table_name(&context.subscriptions_table)
+        .key_condition_expression("campaign_id = :campaign_id")
+        .expression_attribute_values(":campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await?;
+    Ok(serde_dynamo::from_items(items)?)
+}
+
+async fn get_subscription(
+    campaign_id: &str,
+    subscription_id: &str,
+    context: &Context,
+) -> Result<Subscription> {
+    let item = context
+        .dynamodb_client
+        .get_item()
+        .table_name(&context.subscriptions_table)
+        .key("campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .key(
+            "subscription_id",
+            AttributeValue::S(subscription_id.to_string()),
+        )
+        .send()
+        .await?
+        .item
+        .ok_or_else(|| {
+            anyhow!(
+                "Subscription {} not found in campaign {}",
+                subscription_id,
+                campaign_id
+            )
+        })?;
+    Ok(serde_dynamo::from_item(item)?)
+}
+
+pub async fn run(command: SubscribersCommand, context: &Context) -> Result<()> {
+    match command {
+        SubscribersCommand::List { campaign } => {
+            let subscriptions = campaign_subscriptions(&campaign, context).await?;
+            println!(
+                "{:<26} {:<13} {:<22} EMAIL",
+                "SUBSCRIPTION", "STATUS", "CONFIRMED"
+            );
+            for subscription in subscriptions {
+                println!(
+                    "{:<26} {:<13} {:<22} {}",
+                    subscription.subscription_id,
+                    serde_json::to_value(subscription.status())?
+                        .as_str()
+                        .unwrap_or_default(),
+                    format_timestamp(subscription.confirmed_at),
+                    subscription.email
+                );
+            }
+        }
+        SubscribersCommand::Export {
+            campaign,
+            format,
+            status,
+            from,
+            to,
+            output,
+            expires_in,
+        } => {
+            let filter = ExportFilter { status, from, to };
+
+            let count = match output {
+                Some(path) => {
+                    let output: Box<dyn std::io::Write> = if path.as_os_str() == "-" {
+                        Box::new(std::io::stdout().lock())
+                    } else {
+                        Box::new(std::fs::File::create(path)?)
+                    };
+                    export_to_writer(
+                        &context.dynamodb_client,
+                        &context.subscriptions_table,
+                        &campaign,
+                        &filter,
+                        format,
+                        output,
+                    )
+                    .await?
+                }
+                None => {
+                    let s3_key = filter.s3_key(&campaign, format, unix_now());
+                    let count = export_to_s3(
+                        &context.dynamodb_client,
+                        &context.subscriptions_table,
+                        &campaign,
+                        &filter,
+                        format,
+                        &context.s3_client,
+                        &context.resources_bucket,
+                        &s3_key,
+                    )
+                    .await?;
+                    let presigned_request = context
+                        .s3_client
+                        .get_object()
+                        .bucket(&context.resources_bucket)
+                        .key(&s3_key)
+                        .presigned(PresigningConfig::expires_in(Duration::from_secs(
+                            expires_in,
+                        ))?)
+                        .await?;
+                    eprintln!("Uploaded to s3://{}/{}", context.resources_bucket, s3_key);
+                    println!("{}", presigned_request.uri());
+                    count
+                }
+            };
+            eprintln!("Exported {} subscriptions", count);
+        }
+        SubscribersCommand::Import {
+            campaign,
+            file,
+            s3_key,
+            mode,
+            source,
+        } => {
+            let s3_key = match (file, s3_key) {
+                (Some(file), _) => {
+                    let file_name = file
+                        .file_name()
+                        .ok_or_else(|| anyhow!("Invalid file {:?}", file))?
+                        .to_string_lossy();
+                    let s3_key = format!("{}{}", import_prefix(&campaign), file_name);
+                    context
+                        .s3_client
+                        .put_object()
+                        .bucket(&context.resources_bucket)
+                        .key(&s3_key)
+                        .content_type("text/csv")
+                        .body(ByteStream::from_path(&file).await?)
+                        .send()
+                        .await?;
+                    s3_key
+                }
+                (None, Some(s3_key)) => s3_key,
+                (None, None) => return Err(anyhow!("--file or --s3-key is required")),
+            };
+
+            let job = ImportJob {
+                campaign_id: campaign,
+                s3_key,
+                mode,
+                source,
+            };
+            context
+                .lambda_client
+                .invoke()
+                .function_name(&context.import_function)
+                .invocation_type(InvocationType::Event)
+                .payload(Blob::new(serde_json::to_vec(&job)?))
+                .send()
+                .await?;
+            println!(
+                "Import started, the report will be written to s3://{}/{}",
+                context.resources_bucket,
+                job.report_s3_key()
+            );
+        }
+        SubscribersCommand::Delete {
+            campaign,
+            subscription_ids,
+        } => {
+            for subscription_id in subscription_ids {
+                let deleted = context
+                    .dynamodb_client
+                    .delete_item()
+                    .table_name(&context.subscriptions_table)
+                    .key("campaign_id", AttributeValue::S(campaign.clone()))
+                    .key(
+                        "subscription_id",
+                        AttributeValue::S(subscription_id.clone()),
+                    )
+                    .return_values(aws_sdk_dynamodb::types::ReturnValue::AllOld)
+                    .send()
+                    .await?
+                    .attributes
+                    .is_some();
+                match deleted {
+                    true => println!("Deleted {}", subscription_id),
+                    false => println!("{} not found", subscription_id),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn resend(
+    campaign_id: &str,
+    subscription_id: &str,
+    force: bool,
+    context: &Context,
+) -> Result<()> {
+    let subscription = get_subscription(campaign_id, subscription_id, context).await?;
+    match subscription.status() {
+        SubscriptionStatus::Pending => {}
+        SubscriptionStatus::Confirmed if force => {}
+        SubscriptionStatus::Confirmed => {
+            return Err(anyhow!(
+                "Subscription {} is already confirmed, use --force to send the confirmation anyway",
+                subscription_id
+            ))
+        }
+        SubscriptionStatus::Unsubscribed => {
+            return Err(anyhow!("Subscription {} is unsubscribed", subscription_id))
+        }
+    }
+
+    // the same job the subscribe function queues
+    let payload = SubscribeEventPayload {
+        subscription_id: subscription.subscription_id,
+        campaign_id: subscription.campaign_id,
+        email: subscription.email,
+        force,
+        locale: subscription.locale,
+    };
+    let message_id = context
+        .sqs_client
+        .send_message()
+        .queue_url(context.email_queue_url().await?)
+        .message_body(serde_json::to_string(&payload)?)
+        .send()
+        .await?
+        .message_id
+        .unwrap_or_default();
+    println!(
+        "Queued confirmation for {} ({})",
+        subscription_id, message_id
+    );
+
+    Ok(())
+}
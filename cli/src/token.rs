@@ -0,0 +1,87 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use clap::Subcommand;
+use serde_json::Value;
+use shared::{keyring::Keyring, SubscribeConfirmationTokenClaims};
+use std::path::PathBuf;
+
+use crate::format_timestamp;
+
+#[derive(Debug, Subcommand)]
+pub enum TokenCommand {
+    /// Decode a confirmation token, verifying it when a keyring is given
+    Decode {
+        token: String,
+        /// A file holding the keyring JSON document
+        #[arg(long, conflicts_with = "keyring_parameter")]
+        keyring_file: Option<PathBuf>,
+        /// The SSM parameter holding the keyring, as in the
+        /// TokenKeyringParameter of the stack (without the leading slash)
+        #[arg(long)]
+        keyring_parameter: Option<String>,
+    },
+}
+
+async fn load_keyring(
+    keyring_file: Option<PathBuf>,
+    keyring_parameter: Option<String>,
+) -> Result<Option<Keyring>> {
+    let json = match (keyring_file, keyring_parameter) {
+        (Some(path), _) => std::fs::read_to_string(path)?,
+        (None, Some(name)) => {
+            let sdk_config = aws_config::load_from_env().await;
+            aws_sdk_ssm::Client::new(&sdk_config)
+                .get_parameter()
+                .name(format!("/{}", name))
+                .with_decryption(true)
+                .send()
+                .await?
+                .parameter
+                .and_then(|p| p.value)
+                .ok_or_else(|| anyhow!("Parameter {} has no value", name))?
+        }
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(Keyring::from_json(&json)?))
+}
+
+/// Decodes a part of the token without verifying the signature.
+fn decode_part(part: Option<&str>) -> Result<Value> {
+    let part = part.ok_or_else(|| anyhow!("Malformed token"))?;
+    Ok(serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part)?)?)
+}
+
+pub async fn run(command: TokenCommand) -> Result<()> {
+    match command {
+        TokenCommand::Decode {
+            token,
+            keyring_file,
+            keyring_parameter,
+        } => {
+            let mut parts = token.split('.');
+            let header = decode_part(parts.next())?;
+            let claims = decode_part(parts.next())?;
+
+            match load_keyring(keyring_file, keyring_parameter).await? {
+                Some(keyring) => {
+                    keyring
+                        .decode::<SubscribeConfirmationTokenClaims>(&token)
+                        .map_err(|err| anyhow!("Invalid token: {:#}", err))?;
+                    eprintln!("Signature verified");
+                }
+                None => {
+                    eprintln!("Signature not verified (pass --keyring-file or --keyring-parameter)")
+                }
+            }
+
+            println!("{}", serde_json::to_string_pretty(&header)?);
+            println!("{}", serde_json::to_string_pretty(&claims)?);
+            eprintln!(
+                "Expires at {}",
+                format_timestamp(claims.get("exp").and_then(|exp| exp.as_u64()))
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use lambda_http::{tracing, Request};
+use serde::Deserialize;
+use serde_json::json;
+use shared::broadcast::{Broadcast, BroadcastStatus, DeliveryStatus, FanOutPage};
+use shared::unix_now;
+
+use crate::{campaigns, json_response, parse_body, ApiError, ApiResult, Config};
+
+#[derive(Debug, Deserialize)]
+struct CreateBroadcastPayload {
+    subject: String,
+    template_s3_key: String,
+}
+
+fn not_found(broadcast_id: &str) -> ApiError {
+    ApiError::NotFound(format!("Broadcast {} not found", broadcast_id))
+}
+
+async fn load(
+    campaign_id: &str,
+    broadcast_id: &str,
+    config: &Config,
+) -> Result<Option<Broadcast>, lambda_http::Error> {
+    let result = config
+        .dynamodb_client
+        .get_item()
+        .table_name(&config.env.broadcasts_table)
+        .key("campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .key("broadcast_id", AttributeValue::S(broadcast_id.to_string()))
+        .send()
+        .await
+        .map_err(Box::new)?;
+
+    Ok(match result.item {
+        Some(item) => Some(serde_dynamo::from_item(item)?),
+        None => None,
+    })
+}
+
+/// Checks that the campaign exists and is active.
+async fn check_campaign(
+    campaign_id: &str,
+    config: &Config,
+) -> Result<Option<ApiError>, lambda_http::Error> {
+    Ok(match campaigns::load(campaign_id, config).await? {
+        None => Some(campaigns::not_found(campaign_id)),
+        Some(campaign) if !campaign.is_active() => Some(ApiError::Conflict(format!(
+            "Campaign {} is archived",
+            campaign_id
+        ))),
+        Some(_) => None,
+    })
+}
+
+/// `GET /admin/campaigns/{campaign_id}/broadcasts`
+pub async fn list(campaign_id: &str, config: &Config) -> ApiResult {
+    let items = config
+        .dynamodb_client
+        .query()
+        .table_name(&config.env.broadcasts_table)
+        .key_condition_expression("campaign_id = :campaign_id")
+        .expression_attribute_values(":campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(Box::new)?;
+
+    let mut broadcasts: Vec<Broadcast> = serde_dynamo::from_items(items)?;
+    broadcasts.sort_by_key(|broadcast| std::cmp::Reverse(broadcast.created_at));
+
+    Ok(Ok(json_response(
+        200,
+        &json!({ "broadcasts": broadcasts }),
+    )?))
+}
+
+/// `POST /admin/campaigns/{campaign_id}/broadcasts`
+///
+/// Creates a draft broadcast, the template has to be uploaded already.
+pub async fn create(campaign_id: &str, event: &Request, config: &Config) -> ApiResult {
+    let payload: CreateBroadcastPayload = match parse_body(event) {
+        Ok(payload) => payload,
+        Err(err) => return Ok(Err(err)),
+    };
+    let broadcast = Broadcast {
+        campaign_id: campaign_id.to_string(),
+        broadcast_id: cuid::cuid2(),
+        subject: payload.subject,
+        template_s3_key: payload.template_s3_key,
+        status: BroadcastStatus::Draft,
+        recipients: 0,
+        created_at: unix_now(),
+        started_at: None,
+        completed_at: None,
+    };
+    if let Err(errors) = broadcast.validate() {
+        return Ok(Err(ApiError::BadRequest(errors.0)));
+    }
+    if let Some(err) = check_campaign(campaign_id, config).await? {
+        return Ok(Err(err));
+    }
+
+    config
+        .dynamodb_client
+        .put_item()
+        .table_name(&config.env.broadcasts_table)
+        .set_item(Some(serde_dynamo::to_item(&broadcast)?))
+        .send()
+        .await
+        .map_err(Box::new)?;
+
+    Ok(Ok(json_response(201, &broadcast)?))
+}
+
+/// `GET /admin/campaigns/{campaign_id}/broadcasts/{broadcast_id}`
+///
+/// Returns the broadcast with how many deliveries are in each status.
+pub async fn get(campaign_id: &str, broadcast_id: &str, config: &Config) -> ApiResult {
+    let Some(broadcast) = load(campaign_id, broadcast_id, config).await? else {
+        return Ok(Err(not_found(broadcast_id)));
+    };
+
+    let items = config
+        .dynamodb_client
+        .query()
+        .table_name(&config.env.broadcast_deliveries_table)
+        .key_condition_expression("broadcast_id = :broadcast_id")
+        .expression_attribute_values(":broadcast_id", AttributeValue::S(broadcast_id.to_string()))
+        .projection_expression("#status")
+        .expression_attribute_names("#status", "status")
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(Box::new)?;
+    let mut deliveries: HashMap<DeliveryStatus, usize> = HashMap::new();
+    for item in items {
+        if let Some(status) = item.get("status") {
+            let status: DeliveryStatus = serde_dynamo::from_attribute_value(status.clone())?;
+            *deliveries.entry(status).or_default() += 1;
+        }
+    }
+
+    Ok(Ok(json_response(
+        200,
+        &json!({ "broadcast": broadcast, "deliveries": deliveries }),
+    )?))
+}
+
+/// `POST /admin/campaigns/{campaign_id}/broadcasts/{broadcast_id}/send`
+///
+/// Starts sending a draft broadcast: the subscribers are queued page by page
+/// by the fan out function, which reports the broadcast as sent once they
+/// are all queued.
+pub async fn send(campaign_id: &str, broadcast_id: &str, config: &Config) -> ApiResult {
+    if let Some(err) = check_campaign(campaign_id, config).await? {
+        return Ok(Err(err));
+    }
+    if load(campaign_id, broadcast_id, config).await?.is_none() {
+        return Ok(Err(not_found(broadcast_id)));
+    }
+
+    let result = config
+        .dynamodb_client
+        .update_item()
+        .table_name(&config.env.broadcasts_table)
+        .key("campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .key("broadcast_id", AttributeValue::S(broadcast_id.to_string()))
+        .update_expression("SET #status = :sending, started_at = :now")
+        .condition_expression("#status = :draft")
+        .expression_attribute_names("#status", "status")
+        .expression_attribute_values(":sending", AttributeValue::S("sending".to_string()))
+        .expression_attribute_values(":draft", AttributeValue::S("draft".to_string()))
+        .expression_attribute_values(":now", AttributeValue::N(unix_now().to_string()))
+        .return_values(ReturnValue::AllNew)
+        .send()
+        .await;
+    let broadcast: Broadcast = match result {
+        Ok(output) => serde_dynamo::from_item(output.attributes.unwrap_or_default())?,
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) =>
+        {
+            return Ok(Err(ApiError::Conflict(format!(
+                "Broadcast {} was already sent",
+                broadcast_id
+            ))))
+        }
+        Err(err) => return Err(Box::new(err).into()),
+    };
+
+    let page = FanOutPage {
+        campaign_id: campaign_id.to_string(),
+        broadcast_id: broadcast_id.to_string(),
+        start_after: None,
+    };
+    config
+        .sqs_client
+        .send_message()
+        .queue_url(&config.env.broadcast_queue)
+        .message_body(serde_json::to_string(&page)?)
+        .send()
+        .await
+        .map_err(Box::new)?;
+    tracing::info!("Started broadcast: {:?}", page);
+
+    Ok(Ok(json_response(202, &broadcast)?))
+}
@@ -0,0 +1,536 @@
+use std::collections::{BTreeMap, HashMap};
+
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use aws_sdk_s3::presigning::PresigningConfig;
+use lambda_http::{Request, RequestExt};
+use serde::Deserialize;
+use serde_json::json;
+use shared::campaign::{Campaign, CampaignStatus, DripStep};
+use shared::fields::FormField;
+use shared::i18n::Text;
+use shared::import::import_prefix;
+use shared::unix_now;
+
+use crate::{json_response, parse_body, ApiError, ApiResult, Config};
+
+#[derive(Debug, Deserialize)]
+struct CreateCampaignPayload {
+    campaign_id: String,
+    name: String,
+    thank_you_message: String,
+    #[serde(default)]
+    reward_s3_key: String,
+    #[serde(default)]
+    email_template_s3_key: String,
+    sender_email: Option<String>,
+    from_name: Option<String>,
+    reply_to: Option<String>,
+    locale: Option<String>,
+    #[serde(default)]
+    translations: BTreeMap<String, BTreeMap<Text, String>>,
+    #[serde(default)]
+    fields: Vec<FormField>,
+    #[serde(default)]
+    drip: Vec<DripStep>,
+}
+
+/// Only the fields present in the payload are updated (`sender_email`,
+/// `from_name` and `reply_to` are removed with an empty string).
+#[derive(Debug, Deserialize)]
+struct UpdateCampaignPayload {
+    name: Option<String>,
+    thank_you_message: Option<String>,
+    reward_s3_key: Option<String>,
+    email_template_s3_key: Option<String>,
+    sender_email: Option<String>,
+    from_name: Option<String>,
+    reply_to: Option<String>,
+    locale: Option<String>,
+    /// Replaces all the translations
+    translations: Option<BTreeMap<String, BTreeMap<Text, String>>>,
+    /// Replaces all the fields, the values already collected are kept
+    fields: Option<Vec<FormField>>,
+    drip: Option<Vec<DripStep>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum UploadKind {
+    Reward,
+    EmailTemplate,
+    /// A CSV file of subscribers to import
+    Import,
+    /// The HTML template of a broadcast
+    BroadcastTemplate,
+    /// The HTML template of a step of the drip sequence
+    DripTemplate,
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadPayload {
+    kind: UploadKind,
+    file_name: String,
+    content_type: Option<String>,
+}
+
+pub fn not_found(campaign_id: &str) -> ApiError {
+    ApiError::NotFound(format!("Campaign {} not found", campaign_id))
+}
+
+/// Checks that SES can send from an address, i.e. that the address or its
+/// domain is an identity verified for sending.
+async fn verify_sender(
+    sender_email: &str,
+    config: &Config,
+) -> Result<Result<(), ApiError>, lambda_http::Error> {
+    let domain = sender_email.rsplit_once('@').map(|(_, domain)| domain);
+    for identity in [Some(sender_email), domain].into_iter().flatten() {
+        let result = config
+            .ses_client
+            .get_email_identity()
+            .email_identity(identity)
+            .send()
+            .await;
+        match result {
+            Ok(output) if output.verified_for_sending_status => return Ok(Ok(())),
+            Ok(_) => {}
+            Err(err)
+                if err
+                    .as_service_error()
+                    .map(|e| e.is_not_found_exception())
+                    .unwrap_or(false) => {}
+            Err(err) => return Err(Box::new(err).into()),
+        }
+    }
+    Ok(Err(ApiError::BadRequest(vec![format!(
+        "sender_email {} is not a verified SES identity, nor is its domain",
+        sender_email
+    )])))
+}
+
+pub async fn load(
+    campaign_id: &str,
+    config: &Config,
+) -> Result<Option<Campaign>, lambda_http::Error> {
+    let result = config
+        .dynamodb_client
+        .get_item()
+        .table_name(&config.env.campaigns_table)
+        .key("campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .send()
+        .await
+        .map_err(Box::new)?;
+
+    Ok(match result.item {
+        Some(item) => Some(serde_dynamo::from_item(item)?),
+        None => None,
+    })
+}
+
+/// Stores a new campaign, failing with a conflict if it already exists.
+async fn insert(campaign: &Campaign, config: &Config) -> ApiResult {
+    let result = config
+        .dynamodb_client
+        .put_item()
+        .table_name(&config.env.campaigns_table)
+        .set_item(Some(serde_dynamo::to_item(campaign)?))
+        .condition_expression("attribute_not_exists(campaign_id)")
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(Ok(json_response(201, campaign)?)),
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) =>
+        {
+            Ok(Err(ApiError::Conflict(format!(
+                "Campaign {} already exists",
+                campaign.campaign_id
+            ))))
+        }
+        Err(err) => Err(Box::new(err).into()),
+    }
+}
+
+/// Checks that the files a campaign points to were uploaded.
+async fn verify_files(
+    s3_keys: &[(&str, &str)],
+    config: &Config,
+) -> Result<Result<(), ApiError>, lambda_http::Error> {
+    let mut errors = vec![];
+    for (field, s3_key) in s3_keys.iter().filter(|(_, s3_key)| !s3_key.is_empty()) {
+        let result = config
+            .s3_client
+            .head_object()
+            .bucket(&config.env.resources_bucket)
+            .key(*s3_key)
+            .send()
+            .await;
+        match result {
+            Ok(_) => {}
+            Err(err)
+                if err
+                    .as_service_error()
+                    .map(|e| e.is_not_found())
+                    .unwrap_or(false) =>
+            {
+                errors.push(format!("{} {} hasn't been uploaded", field, s3_key));
+            }
+            Err(err) => return Err(Box::new(err).into()),
+        }
+    }
+    Ok(match errors.is_empty() {
+        true => Ok(()),
+        false => Err(ApiError::BadRequest(errors)),
+    })
+}
+
+/// `GET /admin/campaigns[?status=active|archived]`
+pub async fn list(event: &Request, config: &Config) -> ApiResult {
+    let status = match event
+        .query_string_parameters_ref()
+        .and_then(|params| params.first("status"))
+    {
+        Some(status) => match serde_json::from_value::<CampaignStatus>(json!(status)) {
+            Ok(status) => Some(status),
+            Err(_) => {
+                return Ok(Err(ApiError::BadRequest(vec![format!(
+                    "Invalid status {}, expected active or archived",
+                    status
+                )])))
+            }
+        },
+        None => None,
+    };
+
+    let items = config
+        .dynamodb_client
+        .scan()
+        .table_name(&config.env.campaigns_table)
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(Box::new)?;
+
+    let mut campaigns: Vec<Campaign> = serde_dynamo::from_items(items)?;
+    campaigns.retain(|campaign| status.map(|s| campaign.status == s).unwrap_or(true));
+    campaigns.sort_by(|a, b| a.campaign_id.cmp(&b.campaign_id));
+
+    Ok(Ok(json_response(200, &json!({ "campaigns": campaigns }))?))
+}
+
+/// `POST /admin/campaigns`
+pub async fn create(event: &Request, config: &Config) -> ApiResult {
+    let payload: CreateCampaignPayload = match parse_body(event) {
+        Ok(payload) => payload,
+        Err(err) => return Ok(Err(err)),
+    };
+
+    let now = unix_now();
+    let campaign = Campaign {
+        campaign_id: payload.campaign_id,
+        name: payload.name,
+        reward_s3_key: payload.reward_s3_key,
+        email_template_s3_key: payload.email_template_s3_key,
+        thank_you_message: payload.thank_you_message,
+        sender_email: payload.sender_email,
+        from_name: payload.from_name,
+        reply_to: payload.reply_to,
+        locale: payload.locale,
+        translations: payload.translations,
+        fields: payload.fields,
+        drip: payload.drip,
+        status: CampaignStatus::Active,
+        created_at: Some(now),
+        updated_at: Some(now),
+    };
+    if let Err(errors) = campaign.validate() {
+        return Ok(Err(ApiError::BadRequest(errors.0)));
+    }
+    if let Some(sender_email) = &campaign.sender_email {
+        if let Err(err) = verify_sender(sender_email, config).await? {
+            return Ok(Err(err));
+        }
+    }
+
+    insert(&campaign, config).await
+}
+
+/// `GET /admin/campaigns/{campaign_id}`
+pub async fn get(campaign_id: &str, config: &Config) -> ApiResult {
+    Ok(match load(campaign_id, config).await? {
+        Some(campaign) => Ok(json_response(200, &campaign)?),
+        None => Err(not_found(campaign_id)),
+    })
+}
+
+/// `PUT /admin/campaigns/{campaign_id}`
+///
+/// Only the fields of the payload are written, so that a concurrent update
+/// of the others isn't undone, and only if the campaign wasn't archived (or
+/// unarchived) in the meantime.
+pub async fn update(campaign_id: &str, event: &Request, config: &Config) -> ApiResult {
+    let payload: UpdateCampaignPayload = match parse_body(event) {
+        Ok(payload) => payload,
+        Err(err) => return Ok(Err(err)),
+    };
+    let Some(mut campaign) = load(campaign_id, config).await? else {
+        return Ok(Err(not_found(campaign_id)));
+    };
+
+    let mut changed = vec![];
+    if let Some(name) = payload.name {
+        campaign.name = name;
+        changed.push("name");
+    }
+    if let Some(thank_you_message) = payload.thank_you_message {
+        campaign.thank_you_message = thank_you_message;
+        changed.push("thank_you_message");
+    }
+    if let Some(reward_s3_key) = payload.reward_s3_key {
+        campaign.reward_s3_key = reward_s3_key;
+        changed.push("reward_s3_key");
+    }
+    if let Some(email_template_s3_key) = payload.email_template_s3_key {
+        campaign.email_template_s3_key = email_template_s3_key;
+        changed.push("email_template_s3_key");
+    }
+    if let Some(sender_email) = payload.sender_email {
+        campaign.sender_email = Some(sender_email).filter(|sender| !sender.is_empty());
+        changed.push("sender_email");
+    }
+    if let Some(from_name) = payload.from_name {
+        campaign.from_name = Some(from_name).filter(|name| !name.is_empty());
+        changed.push("from_name");
+    }
+    if let Some(reply_to) = payload.reply_to {
+        campaign.reply_to = Some(reply_to).filter(|reply_to| !reply_to.is_empty());
+        changed.push("reply_to");
+    }
+    if let Some(locale) = payload.locale {
+        campaign.locale = Some(locale);
+        changed.push("locale");
+    }
+    if let Some(translations) = payload.translations {
+        campaign.translations = translations;
+        changed.push("translations");
+    }
+    if let Some(fields) = payload.fields {
+        campaign.fields = fields;
+        changed.push("fields");
+    }
+    if let Some(drip) = payload.drip {
+        campaign.drip = drip;
+        changed.push("drip");
+    }
+    campaign.updated_at = Some(unix_now());
+    changed.push("updated_at");
+    if let Err(errors) = campaign.validate() {
+        return Ok(Err(ApiError::BadRequest(errors.0)));
+    }
+    if let (true, Some(sender_email)) = (changed.contains(&"sender_email"), &campaign.sender_email)
+    {
+        if let Err(err) = verify_sender(sender_email, config).await? {
+            return Ok(Err(err));
+        }
+    }
+    let files: Vec<_> = [
+        ("reward_s3_key", campaign.reward_s3_key.as_str()),
+        (
+            "email_template_s3_key",
+            campaign.email_template_s3_key.as_str(),
+        ),
+    ]
+    .into_iter()
+    .filter(|(field, _)| changed.contains(field))
+    .collect();
+    if let Err(err) = verify_files(&files, config).await? {
+        return Ok(Err(err));
+    }
+
+    // the fields left empty are removed
+    let mut item: HashMap<String, AttributeValue> = serde_dynamo::to_item(&campaign)?;
+    let mut set = vec![];
+    let mut remove = vec![];
+    let mut request = config
+        .dynamodb_client
+        .update_item()
+        .table_name(&config.env.campaigns_table)
+        .key("campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .condition_expression("attribute_exists(campaign_id) AND #status = :status")
+        .expression_attribute_names("#status", "status")
+        .expression_attribute_values(
+            ":status",
+            serde_dynamo::to_attribute_value(campaign.status)?,
+        )
+        .return_values(ReturnValue::AllNew);
+    for (i, field) in changed.iter().enumerate() {
+        request = request.expression_attribute_names(format!("#f{}", i), *field);
+        match item.remove(*field) {
+            Some(value) => {
+                set.push(format!("#f{} = :f{}", i, i));
+                request = request.expression_attribute_values(format!(":f{}", i), value);
+            }
+            None => remove.push(format!("#f{}", i)),
+        }
+    }
+    let mut update_expression = format!("SET {}", set.join(", "));
+    if !remove.is_empty() {
+        update_expression.push_str(&format!(" REMOVE {}", remove.join(", ")));
+    }
+    let result = request.update_expression(update_expression).send().await;
+
+    match result {
+        Ok(output) => {
+            let campaign: Campaign =
+                serde_dynamo::from_item(output.attributes.unwrap_or_default())?;
+            Ok(Ok(json_response(200, &campaign)?))
+        }
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) =>
+        {
+            Ok(Err(match load(campaign_id, config).await? {
+                None => not_found(campaign_id),
+                Some(_) => ApiError::Conflict(format!(
+                    "Campaign {} was archived or unarchived in the meantime, try again",
+                    campaign_id
+                )),
+            }))
+        }
+        Err(err) => Err(Box::new(err).into()),
+    }
+}
+
+/// `POST /admin/campaigns/{campaign_id}/archive`
+///
+/// Archived campaigns stop accepting subscriptions, while their subscribers
+/// are kept.
+pub async fn archive(campaign_id: &str, config: &Config) -> ApiResult {
+    let result = config
+        .dynamodb_client
+        .update_item()
+        .table_name(&config.env.campaigns_table)
+        .key("campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .update_expression("SET #status = :status, updated_at = :updated_at")
+        .condition_expression("attribute_exists(campaign_id)")
+        .expression_attribute_names("#status", "status")
+        .expression_attribute_values(":status", AttributeValue::S("archived".to_string()))
+        .expression_attribute_values(":updated_at", AttributeValue::N(unix_now().to_string()))
+        .return_values(ReturnValue::AllNew)
+        .send()
+        .await;
+
+    match result {
+        Ok(output) => {
+            let campaign: Campaign =
+                serde_dynamo::from_item(output.attributes.unwrap_or_default())?;
+            Ok(Ok(json_response(200, &campaign)?))
+        }
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) =>
+        {
+            Ok(Err(not_found(campaign_id)))
+        }
+        Err(err) => Err(Box::new(err).into()),
+    }
+}
+
+/// `DELETE /admin/campaigns/{campaign_id}`
+///
+/// Only the campaign is deleted, its subscriptions and files are kept.
+pub async fn delete(campaign_id: &str, config: &Config) -> ApiResult {
+    let result = config
+        .dynamodb_client
+        .delete_item()
+        .table_name(&config.env.campaigns_table)
+        .key("campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .condition_expression("attribute_exists(campaign_id)")
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(Ok(json_response(200, &json!({ "deleted": campaign_id }))?)),
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) =>
+        {
+            Ok(Err(not_found(campaign_id)))
+        }
+        Err(err) => Err(Box::new(err).into()),
+    }
+}
+
+/// `POST /admin/campaigns/{campaign_id}/uploads`
+///
+/// Returns a presigned URL to `PUT` a file of the campaign to, next to its
+/// other files (or under `imports/` for the subscribers to import). The
+/// campaign is pointed to a new reward file or email template with an
+/// update, once it's uploaded.
+pub async fn create_upload(campaign_id: &str, event: &Request, config: &Config) -> ApiResult {
+    let payload: UploadPayload = match parse_body(event) {
+        Ok(payload) => payload,
+        Err(err) => return Ok(Err(err)),
+    };
+    if payload.file_name.is_empty()
+        || payload.file_name.contains('/')
+        || payload.file_name == ".."
+        || payload.file_name == "."
+    {
+        return Ok(Err(ApiError::BadRequest(vec![
+            "file_name should be a plain file name".to_string(),
+        ])));
+    }
+    if load(campaign_id, config).await?.is_none() {
+        return Ok(Err(not_found(campaign_id)));
+    }
+
+    let s3_key = match payload.kind {
+        UploadKind::Reward => format!("campaigns/{}/reward/{}", campaign_id, payload.file_name),
+        UploadKind::EmailTemplate => format!(
+            "campaigns/{}/email-template/{}",
+            campaign_id, payload.file_name
+        ),
+        UploadKind::Import => format!("{}{}", import_prefix(campaign_id), payload.file_name),
+        UploadKind::BroadcastTemplate => {
+            format!("campaigns/{}/broadcasts/{}", campaign_id, payload.file_name)
+        }
+        UploadKind::DripTemplate => {
+            format!("campaigns/{}/drip/{}", campaign_id, payload.file_name)
+        }
+    };
+
+    let expires_in = *config.env.upload_url_ttl;
+    let presigned_request = config
+        .s3_client
+        .put_object()
+        .bucket(&config.env.resources_bucket)
+        .key(&s3_key)
+        .set_content_type(payload.content_type.clone())
+        .presigned(PresigningConfig::expires_in(expires_in)?)
+        .await?;
+
+    Ok(Ok(json_response(
+        200,
+        &json!({
+            "method": "PUT",
+            "upload_url": presigned_request.uri(),
+            // the headers the upload has to be sent with
+            "headers": presigned_request.headers().collect::<std::collections::HashMap<_, _>>(),
+            "s3_key": s3_key,
+            "expires_in": expires_in.as_secs(),
+        }),
+    )?))
+}
@@ -0,0 +1,89 @@
+use aws_sdk_s3::presigning::PresigningConfig;
+use lambda_http::{tracing, Request};
+use serde::Deserialize;
+use serde_json::json;
+use shared::export::{export_to_s3, parse_timestamp, ExportFilter, ExportFormat};
+use shared::subscription::SubscriptionStatus;
+use shared::unix_now;
+
+use crate::{campaigns, json_response, parse_body, ApiError, ApiResult, Config};
+
+#[derive(Debug, Deserialize)]
+struct ExportPayload {
+    #[serde(default = "default_format")]
+    format: ExportFormat,
+    status: Option<SubscriptionStatus>,
+    /// RFC 3339 date (`2024-06-01`) or date and time, included
+    from: Option<String>,
+    /// RFC 3339 date or date and time, excluded
+    to: Option<String>,
+}
+
+fn default_format() -> ExportFormat {
+    ExportFormat::Csv
+}
+
+/// `POST /admin/campaigns/{campaign_id}/exports`
+///
+/// Exports the subscriptions of the campaign to the resources bucket and
+/// returns a presigned URL to download the file.
+pub async fn create(campaign_id: &str, event: &Request, config: &Config) -> ApiResult {
+    let payload: ExportPayload = match parse_body(event) {
+        Ok(payload) => payload,
+        Err(err) => return Ok(Err(err)),
+    };
+    let mut errors = vec![];
+    let mut parse = |date: Option<String>| {
+        date.and_then(|date| match parse_timestamp(&date) {
+            Ok(timestamp) => Some(timestamp),
+            Err(err) => {
+                errors.push(err.to_string());
+                None
+            }
+        })
+    };
+    let filter = ExportFilter {
+        status: payload.status,
+        from: parse(payload.from),
+        to: parse(payload.to),
+    };
+    if !errors.is_empty() {
+        return Ok(Err(ApiError::BadRequest(errors)));
+    }
+    if campaigns::load(campaign_id, config).await?.is_none() {
+        return Ok(Err(campaigns::not_found(campaign_id)));
+    }
+
+    let s3_key = filter.s3_key(campaign_id, payload.format, unix_now());
+    let count = export_to_s3(
+        &config.dynamodb_client,
+        &config.env.subscriptions_table,
+        campaign_id,
+        &filter,
+        payload.format,
+        &config.s3_client,
+        &config.env.resources_bucket,
+        &s3_key,
+    )
+    .await?;
+
+    let expires_in = *config.env.export_url_ttl;
+    let presigned_request = config
+        .s3_client
+        .get_object()
+        .bucket(&config.env.resources_bucket)
+        .key(&s3_key)
+        .presigned(PresigningConfig::expires_in(expires_in)?)
+        .await?;
+    tracing::info!(s3_key, count, "Exported subscriptions");
+
+    Ok(Ok(json_response(
+        200,
+        &json!({
+            "s3_key": s3_key,
+            "count": count,
+            "download_url": presigned_request.uri(),
+            "expires_in": expires_in.as_secs(),
+        }),
+    )?))
+}
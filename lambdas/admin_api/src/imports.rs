@@ -0,0 +1,67 @@
+use aws_sdk_lambda::primitives::Blob;
+use aws_sdk_lambda::types::InvocationType;
+use lambda_http::{tracing, Request};
+use serde::Deserialize;
+use serde_json::json;
+use shared::import::{import_prefix, ImportJob, ImportMode};
+
+use crate::{campaigns, json_response, parse_body, ApiError, ApiResult, Config};
+
+#[derive(Debug, Deserialize)]
+struct ImportPayload {
+    s3_key: String,
+    mode: ImportMode,
+    source: Option<String>,
+}
+
+/// `POST /admin/campaigns/{campaign_id}/imports`
+///
+/// Starts importing a CSV file from the resources bucket, the report is
+/// written next to it when the import completes.
+pub async fn create(campaign_id: &str, event: &Request, config: &Config) -> ApiResult {
+    let payload: ImportPayload = match parse_body(event) {
+        Ok(payload) => payload,
+        Err(err) => return Ok(Err(err)),
+    };
+    let job = ImportJob {
+        campaign_id: campaign_id.to_string(),
+        s3_key: payload.s3_key,
+        mode: payload.mode,
+        source: payload.source,
+    };
+    if !job.has_valid_s3_key() {
+        return Ok(Err(ApiError::BadRequest(vec![format!(
+            "s3_key should be a file uploaded under {}",
+            import_prefix(campaign_id)
+        )])));
+    }
+    match campaigns::load(campaign_id, config).await? {
+        None => return Ok(Err(campaigns::not_found(campaign_id))),
+        Some(campaign) if !campaign.is_active() => {
+            return Ok(Err(ApiError::Conflict(format!(
+                "Campaign {} is archived",
+                campaign_id
+            ))))
+        }
+        Some(_) => {}
+    }
+
+    config
+        .lambda_client
+        .invoke()
+        .function_name(&config.env.import_function)
+        .invocation_type(InvocationType::Event)
+        .payload(Blob::new(serde_json::to_vec(&job)?))
+        .send()
+        .await
+        .map_err(Box::new)?;
+    tracing::info!("Started import: {:?}", job);
+
+    Ok(Ok(json_response(
+        202,
+        &json!({
+            "s3_key": job.s3_key,
+            "report_s3_key": job.report_s3_key(),
+        }),
+    )?))
+}
@@ -0,0 +1,139 @@
+mod broadcasts;
+mod campaigns;
+mod exports;
+mod imports;
+
+use lambda_http::{run, service_fn, tracing, Body, Error, Request, RequestExt, Response};
+use sam_env_macros::sam_env;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+
+#[sam_env(template = "../../template.yaml")]
+struct SamEnv;
+
+struct Config {
+    env: SamEnv,
+    dynamodb_client: aws_sdk_dynamodb::Client,
+    s3_client: aws_sdk_s3::Client,
+    lambda_client: aws_sdk_lambda::Client,
+    sqs_client: aws_sdk_sqs::Client,
+    ses_client: aws_sdk_sesv2::Client,
+}
+
+/// The errors reported to the caller, anything else is a 500.
+#[derive(Debug)]
+enum ApiError {
+    BadRequest(Vec<String>),
+    NotFound(String),
+    Conflict(String),
+}
+
+impl ApiError {
+    fn into_response(self) -> Result<Response<Body>, Error> {
+        let (status, body) = match self {
+            ApiError::BadRequest(errors) => (400, json!({ "errors": errors })),
+            ApiError::NotFound(error) => (404, json!({ "error": error })),
+            ApiError::Conflict(error) => (409, json!({ "error": error })),
+        };
+        json_response(status, &body)
+    }
+}
+
+type ApiResult = Result<Result<Response<Body>, ApiError>, Error>;
+
+fn json_response(status: u16, body: &impl Serialize) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(serde_json::to_string(body)?.into())
+        .map_err(Box::new)?)
+}
+
+fn parse_body<T: DeserializeOwned>(event: &Request) -> Result<T, ApiError> {
+    serde_json::from_slice(event.body())
+        .map_err(|err| ApiError::BadRequest(vec![format!("Invalid payload: {}", err)]))
+}
+
+async fn function_handler(event: Request, config: &Config) -> Result<Response<Body>, Error> {
+    // every route is behind the IAM authorizer of the API
+    let campaign_id = event
+        .path_parameters_ref()
+        .and_then(|params| params.first("campaign_id"))
+        .map(|campaign_id| campaign_id.to_string());
+    let broadcast_id = event
+        .path_parameters_ref()
+        .and_then(|params| params.first("broadcast_id"))
+        .map(|broadcast_id| broadcast_id.to_string());
+    let action = event.raw_http_path().rsplit('/').next().unwrap_or_default();
+
+    let result = match (
+        event.method().as_str(),
+        campaign_id.as_deref(),
+        broadcast_id.as_deref(),
+        action,
+    ) {
+        ("GET", None, _, _) => campaigns::list(&event, config).await,
+        ("POST", None, _, _) => campaigns::create(&event, config).await,
+        ("GET", Some(campaign_id), None, "broadcasts") => {
+            broadcasts::list(campaign_id, config).await
+        }
+        ("POST", Some(campaign_id), None, "broadcasts") => {
+            broadcasts::create(campaign_id, &event, config).await
+        }
+        ("GET", Some(campaign_id), Some(broadcast_id), _) => {
+            broadcasts::get(campaign_id, broadcast_id, config).await
+        }
+        ("POST", Some(campaign_id), Some(broadcast_id), "send") => {
+            broadcasts::send(campaign_id, broadcast_id, config).await
+        }
+        ("GET", Some(campaign_id), None, _) => campaigns::get(campaign_id, config).await,
+        ("PUT", Some(campaign_id), None, _) => campaigns::update(campaign_id, &event, config).await,
+        ("DELETE", Some(campaign_id), None, _) => campaigns::delete(campaign_id, config).await,
+        ("POST", Some(campaign_id), None, "archive") => {
+            campaigns::archive(campaign_id, config).await
+        }
+        ("POST", Some(campaign_id), None, "uploads") => {
+            campaigns::create_upload(campaign_id, &event, config).await
+        }
+        ("POST", Some(campaign_id), None, "exports") => {
+            exports::create(campaign_id, &event, config).await
+        }
+        ("POST", Some(campaign_id), None, "imports") => {
+            imports::create(campaign_id, &event, config).await
+        }
+        _ => Ok(Err(ApiError::NotFound("Route not found".to_string()))),
+    }?;
+
+    match result {
+        Ok(response) => Ok(response),
+        Err(err) => {
+            tracing::info!("Rejected admin request: {:?}", err);
+            err.into_response()
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let env = SamEnv::from_env()?;
+
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = aws_sdk_dynamodb::Client::new(&config);
+    let s3_client = aws_sdk_s3::Client::new(&config);
+    let lambda_client = aws_sdk_lambda::Client::new(&config);
+    let sqs_client = aws_sdk_sqs::Client::new(&config);
+    let ses_client = aws_sdk_sesv2::Client::new(&config);
+
+    let config = Config {
+        env,
+        dynamodb_client,
+        s3_client,
+        lambda_client,
+        sqs_client,
+        ses_client,
+    };
+
+    tracing::init_default_subscriber();
+
+    run(service_fn(|event| function_handler(event, &config))).await
+}
@@ -0,0 +1,286 @@
+use aws_lambda_events::event::sqs::SqsEvent;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_sqs::types::SendMessageBatchRequestEntry;
+use lambda_runtime::{run, service_fn, tracing, Error, LambdaEvent};
+use sam_env_macros::sam_env;
+use shared::broadcast::{
+    Broadcast, BroadcastDelivery, BroadcastStatus, DeliveryStatus, FanOutPage,
+};
+use shared::sqs::{send_batch, SEND_MESSAGE_BATCH_SIZE};
+use shared::subscription::Subscription;
+use shared::{unix_now, BroadcastEmail, EmailJob};
+
+#[sam_env(template = "../../template.yaml")]
+struct SamEnv;
+
+struct Config {
+    env: SamEnv,
+    dynamodb_client: aws_sdk_dynamodb::Client,
+    sqs_client: aws_sdk_sqs::Client,
+}
+
+// the longest delay SQS supports
+const MAX_DELAY_SECONDS: u64 = 900;
+
+/// How many subscribers a page has: `FAN_OUT_PAGE_SIZE`, as long as they
+/// can all be sent within the longest delay at the configured rate.
+fn page_size(config: &Config) -> u32 {
+    let max_send_rate = config.env.max_send_rate.max(1);
+    config
+        .env
+        .fan_out_page_size
+        .min(MAX_DELAY_SECONDS as u32 * max_send_rate)
+}
+
+/// How long to wait before queueing the `index`-th email of the page, so
+/// that the emails are sent at the configured rate.
+fn delay_seconds(index: usize, config: &Config) -> u64 {
+    (index as u64 / config.env.max_send_rate.max(1) as u64).min(MAX_DELAY_SECONDS)
+}
+
+async fn load_broadcast(page: &FanOutPage, config: &Config) -> Result<Option<Broadcast>, Error> {
+    let item = config
+        .dynamodb_client
+        .get_item()
+        .table_name(&config.env.broadcasts_table)
+        .key("campaign_id", AttributeValue::S(page.campaign_id.clone()))
+        .key("broadcast_id", AttributeValue::S(page.broadcast_id.clone()))
+        .send()
+        .await
+        .map_err(Box::new)?
+        .item;
+    Ok(match item {
+        Some(item) => Some(serde_dynamo::from_item(item)?),
+        None => None,
+    })
+}
+
+/// Records the delivery as queued, returning false when it was already
+/// handled (a page processed again after a failure). The queued ones are
+/// queued again, as the email may not have made it to the queue: the
+/// sender skips the deliveries that are not queued anymore.
+async fn create_delivery(delivery: &BroadcastDelivery, config: &Config) -> Result<bool, Error> {
+    let result = config
+        .dynamodb_client
+        .put_item()
+        .table_name(&config.env.broadcast_deliveries_table)
+        .set_item(Some(serde_dynamo::to_item(delivery)?))
+        .condition_expression("attribute_not_exists(subscription_id) OR #status = :queued")
+        .expression_attribute_names("#status", "status")
+        .expression_attribute_values(":queued", AttributeValue::S("queued".to_string()))
+        .send()
+        .await;
+    match result {
+        Ok(_) => Ok(true),
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) =>
+        {
+            Ok(false)
+        }
+        Err(err) => Err(Box::new(err).into()),
+    }
+}
+
+/// Queues the broadcast for a page of subscribers and then either the next
+/// page, delayed by the time it takes to send this one, or marks the
+/// broadcast as sent.
+async fn fan_out(page: FanOutPage, config: &Config) -> Result<(), Error> {
+    let Some(broadcast) = load_broadcast(&page, config).await? else {
+        tracing::warn!("Broadcast not found: {:?}", page);
+        return Ok(());
+    };
+    if broadcast.status != BroadcastStatus::Sending {
+        tracing::info!("Broadcast is not sending, skipping: {:?}", page);
+        return Ok(());
+    }
+
+    let output = config
+        .dynamodb_client
+        .query()
+        .table_name(&config.env.subscriptions_table)
+        .key_condition_expression("campaign_id = :campaign_id")
+        .expression_attribute_values(":campaign_id", AttributeValue::S(page.campaign_id.clone()))
+        .limit(page_size(config) as i32)
+        .set_exclusive_start_key(page.start_after.as_ref().map(|start_after| {
+            [
+                (
+                    "campaign_id".to_string(),
+                    AttributeValue::S(page.campaign_id.clone()),
+                ),
+                (
+                    "subscription_id".to_string(),
+                    AttributeValue::S(start_after.clone()),
+                ),
+            ]
+            .into()
+        }))
+        .send()
+        .await
+        .map_err(Box::new)?;
+    let subscriptions: Vec<Subscription> =
+        serde_dynamo::from_items(output.items.unwrap_or_default())?;
+    let last_subscription_id = output
+        .last_evaluated_key
+        .as_ref()
+        .and_then(|key| key.get("subscription_id"))
+        .and_then(|id| id.as_s().ok())
+        .cloned();
+
+    let now = unix_now();
+    let mut emails = vec![];
+    for subscription in subscriptions {
+        if !subscription.receives_broadcasts() {
+            continue;
+        }
+        let delivery = BroadcastDelivery {
+            broadcast_id: broadcast.broadcast_id.clone(),
+            subscription_id: subscription.subscription_id.clone(),
+            campaign_id: broadcast.campaign_id.clone(),
+            email: subscription.email.clone(),
+            status: DeliveryStatus::Queued,
+            queued_at: now,
+            sent_at: None,
+            ses_message_id: None,
+            error: None,
+        };
+        if create_delivery(&delivery, config).await? {
+            emails.push(EmailJob::Broadcast(BroadcastEmail {
+                campaign_id: delivery.campaign_id,
+                broadcast_id: delivery.broadcast_id,
+                subscription_id: delivery.subscription_id,
+                email: delivery.email,
+            }));
+        }
+    }
+
+    for (chunk_index, chunk) in emails.chunks(SEND_MESSAGE_BATCH_SIZE).enumerate() {
+        let entries = chunk
+            .iter()
+            .enumerate()
+            .map(|(i, email)| {
+                let index = chunk_index * SEND_MESSAGE_BATCH_SIZE + i;
+                Ok(SendMessageBatchRequestEntry::builder()
+                    .id(i.to_string())
+                    .message_body(serde_json::to_string(email)?)
+                    .delay_seconds(delay_seconds(index, config) as i32)
+                    .build()?)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        send_batch(&config.sqs_client, &config.env.email_queue, entries).await?;
+    }
+
+    tracing::info!(queued = emails.len(), "Queued broadcast page: {:?}", page);
+
+    // the pages go in the order of the subscription ids, so the page last
+    // counted tells whether this one is a redelivery, already counted
+    let page_id = page.start_after.clone().unwrap_or_default();
+    let update = |broadcast: &Broadcast, expression: &str| {
+        config
+            .dynamodb_client
+            .update_item()
+            .table_name(&config.env.broadcasts_table)
+            .key(
+                "campaign_id",
+                AttributeValue::S(broadcast.campaign_id.clone()),
+            )
+            .key(
+                "broadcast_id",
+                AttributeValue::S(broadcast.broadcast_id.clone()),
+            )
+            .update_expression(expression)
+            .condition_expression("attribute_not_exists(counted_page) OR counted_page < :page")
+            .expression_attribute_values(":count", AttributeValue::N(emails.len().to_string()))
+            .expression_attribute_values(":page", AttributeValue::S(page_id.clone()))
+    };
+    // the last page marks the broadcast as sent
+    let counted = match last_subscription_id {
+        Some(_) => {
+            update(&broadcast, "ADD recipients :count SET counted_page = :page")
+                .send()
+                .await
+        }
+        None => {
+            update(
+                &broadcast,
+                "ADD recipients :count \
+                 SET counted_page = :page, #status = :sent, completed_at = :now",
+            )
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(":sent", AttributeValue::S("sent".to_string()))
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .send()
+            .await
+        }
+    };
+    match counted {
+        Ok(_) => {}
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) =>
+        {
+            tracing::info!("Page already counted: {:?}", page);
+        }
+        Err(err) => return Err(Box::new(err).into()),
+    }
+
+    match last_subscription_id {
+        Some(start_after) => {
+            let next_page = FanOutPage {
+                start_after: Some(start_after),
+                ..page
+            };
+            config
+                .sqs_client
+                .send_message()
+                .queue_url(&config.env.broadcast_queue)
+                .message_body(serde_json::to_string(&next_page)?)
+                .delay_seconds(delay_seconds(emails.len(), config) as i32)
+                .send()
+                .await
+                .map_err(Box::new)?;
+        }
+        None => {
+            tracing::info!(
+                campaign_id = broadcast.campaign_id,
+                broadcast_id = broadcast.broadcast_id,
+                "Broadcast fully queued"
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn function_handler(event: LambdaEvent<SqsEvent>, config: &Config) -> Result<(), Error> {
+    for record in event.payload.records {
+        if let Some(body) = record.body {
+            let page: FanOutPage = serde_json::from_str(&body)?;
+            fan_out(page, config).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let env = SamEnv::from_env()?;
+
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = aws_sdk_dynamodb::Client::new(&config);
+    let sqs_client = aws_sdk_sqs::Client::new(&config);
+
+    let config = Config {
+        env,
+        dynamodb_client,
+        sqs_client,
+    };
+
+    tracing::init_default_subscriber();
+
+    run(service_fn(|event| function_handler(event, &config))).await
+}
@@ -1,19 +1,24 @@
-use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValuesOnConditionCheckFailure};
 use aws_sdk_s3::presigning::PresigningConfig;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
 use lambda_http::{
     http::StatusCode, run, service_fn, tracing, Body, Error, Request, RequestExt, Response,
 };
-use shared::SubscribeConfirmationTokenClaims;
-use std::{env, time::Duration};
+use shared::campaign::Campaign;
+use shared::i18n::Text;
+use shared::{keyring::Keyring, unix_now, DripEmail, EmailJob, SubscribeConfirmationTokenClaims};
 
-include!(concat!(env!("OUT_DIR"), "/sam_env.rs"));
+use sam_env_macros::sam_env;
+
+#[sam_env(template = "../../template.yaml")]
+struct SamEnv;
 
 struct Config {
     env: SamEnv,
     dynamodb_client: aws_sdk_dynamodb::Client,
     s3_client: aws_sdk_s3::Client,
-    decoding_key: DecodingKey,
+    sqs_client: aws_sdk_sqs::Client,
+    keyring: Keyring,
 }
 
 async fn function_handler(event: Request, config: &Config) -> Result<Response<Body>, Error> {
@@ -30,12 +35,10 @@ async fn function_handler(event: Request, config: &Config) -> Result<Response<Bo
         .query_string_parameters_ref()
         .and_then(|params| params.first("token"))
         .and_then(|token| {
-            decode::<SubscribeConfirmationTokenClaims>(
-                token,
-                &config.decoding_key,
-                &Validation::new(Algorithm::HS256),
-            )
-            .ok()
+            config
+                .keyring
+                .decode::<SubscribeConfirmationTokenClaims>(token)
+                .ok()
         });
 
     let token_data = match token {
@@ -43,7 +46,7 @@ async fn function_handler(event: Request, config: &Config) -> Result<Response<Bo
         None => {
             return Ok(Response::builder()
                 .status(400)
-                .body("Invalid token".into())
+                .body(Text::InvalidToken.default_text().into())
                 .map_err(Box::new)?)
         }
     };
@@ -55,35 +58,109 @@ async fn function_handler(event: Request, config: &Config) -> Result<Response<Bo
         .table_name(config.env.campaigns_table.clone())
         .key(
             "campaign_id",
-            AttributeValue::S(token_data.claims.campaign_id.clone()),
+            AttributeValue::S(token_data.campaign_id.clone()),
         )
         .send()
         .await
         .map_err(Box::new)?;
 
-    if get_campaign_result.item.is_none() {
+    let Some(campaign_item) = get_campaign_result.item else {
         return Ok(Response::builder()
             .status(400)
-            .body("Invalid campaign".into())
+            .body(Text::CampaignNotFound.default_text().into())
             .map_err(Box::new)?);
+    };
+    let campaign: Campaign = serde_dynamo::from_item(campaign_item)?;
+
+    // 3. update subscription record (confirmed) and don't expire it anymore,
+    //    the first time only. With a drip sequence, no step sent marks it as
+    //    to be started
+    let mut update = "SET confirmed_at = :now".to_string();
+    let mut request = config
+        .dynamodb_client
+        .update_item()
+        .table_name(&config.env.subscriptions_table)
+        .key(
+            "campaign_id",
+            AttributeValue::S(token_data.campaign_id.clone()),
+        )
+        .key(
+            "subscription_id",
+            AttributeValue::S(token_data.subscription_id.clone()),
+        )
+        .condition_expression(
+            "attribute_exists(subscription_id) AND attribute_not_exists(confirmed_at)",
+        )
+        .expression_attribute_values(":now", AttributeValue::N(unix_now().to_string()))
+        .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
+    if !campaign.drip.is_empty() {
+        update.push_str(", drip_steps_sent = :zero");
+        request = request.expression_attribute_values(":zero", AttributeValue::N("0".to_string()));
     }
+    let confirm_result = request
+        .update_expression(update + " REMOVE expires_at, pending_sent_at")
+        .send()
+        .await;
+
+    // the subscription is returned when it was confirmed already
+    let start_drip = match confirm_result.map_err(|err| err.into_service_error()) {
+        Ok(_) => !campaign.drip.is_empty(),
+        Err(UpdateItemError::ConditionalCheckFailedException(failed)) => match failed.item {
+            // until its first step is sent, confirming again (e.g. after
+            // failing to queue it below) starts the drip again, the sender
+            // sending each step once
+            Some(subscription) => {
+                subscription.get("drip_steps_sent") == Some(&AttributeValue::N("0".to_string()))
+            }
+            None => {
+                // in the locale the subscriber chose, the subscription being
+                // gone (e.g. expired)
+                let accept_language = event
+                    .headers()
+                    .get("accept-language")
+                    .and_then(|value| value.to_str().ok());
+                let locale =
+                    campaign.negotiate_locale(token_data.locale.as_deref(), accept_language);
+                return Ok(Response::builder()
+                    .status(400)
+                    .header("content-language", &locale)
+                    .body(
+                        campaign
+                            .text(&locale, Text::InvalidSubscription)
+                            .to_string()
+                            .into(),
+                    )
+                    .map_err(Box::new)?);
+            }
+        },
+        Err(err) => return Err(Box::new(err).into()),
+    };
 
-    // TODO: use https://docs.rs/serde_dynamo/latest/serde_dynamo/ to deserialize the item in a better/cooler way
-    let campaign_item = get_campaign_result.item.unwrap();
-    let reward_s3_key = campaign_item
-        .get("reward_s3_key")
-        .unwrap()
-        .as_s()
-        .unwrap()
-        .as_str();
+    // 4. start the drip sequence, the first step is scheduled by the sender
+    if start_drip {
+        let job = EmailJob::Drip(DripEmail {
+            campaign_id: token_data.campaign_id.clone(),
+            subscription_id: token_data.subscription_id.clone(),
+            email: token_data.email.clone(),
+            step: 0,
+        });
+        config
+            .sqs_client
+            .send_message()
+            .queue_url(&config.env.email_queue)
+            .message_body(serde_json::to_string(&job)?)
+            .send()
+            .await
+            .map_err(Box::new)?;
+    }
 
     // Create pre-signed URL to get the reward file
-    let expires_in = Duration::from_secs(60);
+    let expires_in = *config.env.reward_url_ttl;
     let presigned_request = config
         .s3_client
         .get_object()
         .bucket(&config.env.resources_bucket)
-        .key(reward_s3_key)
+        .key(&campaign.reward_s3_key)
         .presigned(PresigningConfig::expires_in(expires_in)?)
         .await?;
 
@@ -99,18 +176,21 @@ async fn function_handler(event: Request, config: &Config) -> Result<Response<Bo
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let env = SamEnv::init_from_env().unwrap();
+    let env = SamEnv::from_env()?;
 
     let config = aws_config::load_from_env().await;
     let dynamodb_client = aws_sdk_dynamodb::Client::new(&config);
     let s3_client = aws_sdk_s3::Client::new(&config);
-    let decoding_key = DecodingKey::from_secret(env.token_secret.as_ref());
+    let sqs_client = aws_sdk_sqs::Client::new(&config);
+    let env = env.resolve_secrets(&config).await?;
+    let keyring = Keyring::from_json(&env.token_keyring)?;
 
     let config = Config {
         env,
         dynamodb_client,
         s3_client,
-        decoding_key,
+        sqs_client,
+        keyring,
     };
 
     tracing::init_default_subscriber();
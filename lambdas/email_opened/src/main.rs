@@ -1,5 +1,8 @@
 use lambda_http::{run, service_fn, tracing, Body, Error, Request, RequestExt, Response};
-include!(concat!(env!("OUT_DIR"), "/sam_env.rs"));
+use sam_env_macros::sam_env;
+
+#[sam_env(template = "../../template.yaml")]
+struct SamEnv;
 
 /// This is the main body for the function.
 /// Write your code inside it.
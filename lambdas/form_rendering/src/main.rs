@@ -1,20 +1,83 @@
+use aws_sdk_dynamodb::types::AttributeValue;
 use lambda_http::{
-    request::RequestContext, run, service_fn, tracing, Body, Error, Request, RequestExt,
-    RequestPayloadExt, Response,
+    request::RequestContext, run, service_fn, tracing, Body, Error, Request, RequestExt, Response,
 };
+use sam_env_macros::sam_env;
+use shared::campaign::Campaign;
+use shared::fields::FieldKind;
+use shared::i18n::Text;
+use shared::render::escape_html;
 
-fn create_form(target_url: &str) -> String {
+#[sam_env(template = "../../template.yaml")]
+struct SamEnv;
+
+struct Config {
+    env: SamEnv,
+    dynamodb_client: aws_sdk_dynamodb::Client,
+}
+
+/// The inputs of the extra fields of the campaign, with the hidden ones
+/// filled from the query string or the headers of the request.
+fn field_inputs(campaign: &Campaign, event: &Request) -> String {
+    let query = event.query_string_parameters_ref();
+    let mut inputs = String::new();
+    for field in &campaign.fields {
+        let name = escape_html(&field.name);
+        let label = escape_html(&field.label);
+        let required = match field.required {
+            true => " required",
+            false => "",
+        };
+        let input = match field.kind {
+            FieldKind::Text => format!(
+                r#"<label for="{name}">{label}</label>
+                <input{required} type="text" id="{name}" name="{name}">"#
+            ),
+            FieldKind::Number => format!(
+                r#"<label for="{name}">{label}</label>
+                <input{required} type="number" step="any" id="{name}" name="{name}">"#
+            ),
+            FieldKind::Checkbox => format!(
+                r#"<input{required} type="checkbox" id="{name}" name="{name}" value="on">
+                <label for="{name}">{label}</label>"#
+            ),
+            FieldKind::Hidden => {
+                let value = query
+                    .and_then(|params| params.first(&field.name))
+                    .or_else(|| {
+                        event
+                            .headers()
+                            .get(field.name.as_str())
+                            .and_then(|value| value.to_str().ok())
+                    })
+                    .unwrap_or_default();
+                let value = escape_html(value);
+                format!(r#"<input type="hidden" name="{name}" value="{value}">"#)
+            }
+        };
+        inputs.push_str("\n                ");
+        inputs.push_str(&input);
+    }
+    inputs
+}
+
+fn create_form(target_url: &str, campaign: &Campaign, locale: &str, fields: &str) -> String {
+    let title = escape_html(campaign.text(locale, Text::FormTitle));
+    let email_label = escape_html(campaign.text(locale, Text::FormEmailLabel));
+    let submit = escape_html(campaign.text(locale, Text::FormSubmit));
+    let locale = escape_html(locale);
     format!(
         r#"
-    <html>
+    <html lang="{locale}">
         <head>
-            <title>Subscribe to our newsletter</title>
+            <title>{title}</title>
         </head>
         <body>
             <form action="{target_url}" method="post">
-                <label for="email">Email:</label>
-                <input required type="email" id="email" name="email">
-                <input type="submit" value="Submit">
+                <input type="hidden" name="locale" value="{locale}">
+                <label for="email">{email_label}</label>
+                <input required type="email" id="email" name="email">{fields}
+                <input type="submit" value="{submit}">
             </form>
         </body>
     </html>
@@ -22,7 +85,7 @@ fn create_form(target_url: &str) -> String {
     )
 }
 
-async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+async fn function_handler(event: Request, config: &Config) -> Result<Response<Body>, Error> {
     let request_context = event.request_context();
     let form_submit_url = match request_context {
         RequestContext::ApiGatewayV2(api_gateway_v2) => {
@@ -34,13 +97,54 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
             return Err("Unsupported request context".into());
         }
     };
-    let form_html = create_form(&form_submit_url);
+
+    let campaign_id = event
+        .path_parameters_ref()
+        .and_then(|params| params.first("campaign_id"))
+        .unwrap_or_default();
+    let campaign: Option<Campaign> = config
+        .dynamodb_client
+        .get_item()
+        .table_name(&config.env.campaigns_table)
+        .key("campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .send()
+        .await
+        .map_err(Box::new)?
+        .item
+        .map(serde_dynamo::from_item)
+        .transpose()?;
+    let Some(campaign) = campaign.filter(|c| c.is_active()) else {
+        return Ok(Response::builder()
+            .status(404)
+            .header("content-type", "text/html")
+            .body(Text::CampaignNotFound.default_text().into())
+            .map_err(Box::new)?);
+    };
+
+    // `?locale=` picks one of the locales of the campaign, otherwise it's
+    // negotiated with the browser
+    let requested = event
+        .query_string_parameters_ref()
+        .and_then(|params| params.first("locale"));
+    let accept_language = event
+        .headers()
+        .get("accept-language")
+        .and_then(|value| value.to_str().ok());
+    let locale = campaign.negotiate_locale(requested, accept_language);
+    let form_html = create_form(
+        &form_submit_url,
+        &campaign,
+        &locale,
+        &field_inputs(&campaign, &event),
+    );
 
     // Return something that implements IntoResponse.
     // It will be serialized to the right response event automatically by the runtime
     let resp = Response::builder()
         .status(200)
         .header("content-type", "text/html")
+        .header("content-language", &locale)
+        .header("vary", "accept-language")
         .body(form_html.into())
         .map_err(Box::new)?;
     Ok(resp)
@@ -48,7 +152,17 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
+    let env = SamEnv::from_env()?;
+
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = aws_sdk_dynamodb::Client::new(&config);
+
+    let config = Config {
+        env,
+        dynamodb_client,
+    };
+
     tracing::init_default_subscriber();
 
-    run(service_fn(function_handler)).await
+    run(service_fn(|event| function_handler(event, &config))).await
 }
@@ -0,0 +1,447 @@
+use std::collections::{HashMap, HashSet};
+
+use aws_sdk_dynamodb::types::{AttributeValue, KeysAndAttributes, PutRequest, WriteRequest};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_sqs::types::SendMessageBatchRequestEntry;
+use lambda_runtime::{run, service_fn, tracing, Error, LambdaEvent};
+use sam_env_macros::sam_env;
+use shared::campaign::Campaign;
+use shared::email::normalize_email;
+use shared::export::parse_timestamp;
+use shared::import::{import_prefix, ImportJob, ImportMode, ImportReport, InvalidRow};
+use shared::sqs::{backoff, send_batch, MAX_ATTEMPTS, SEND_MESSAGE_BATCH_SIZE};
+use shared::subscription::{ImportProvenance, Subscription};
+use shared::{unix_now, SubscribeEventPayload};
+
+#[sam_env(template = "../../template.yaml")]
+struct SamEnv;
+
+struct Config {
+    env: SamEnv,
+    dynamodb_client: aws_sdk_dynamodb::Client,
+    s3_client: aws_sdk_s3::Client,
+    sqs_client: aws_sdk_sqs::Client,
+}
+
+// the limit of BatchWriteItem
+const BATCH_WRITE_SIZE: usize = 25;
+// the limit of BatchGetItem
+const BATCH_GET_SIZE: usize = 100;
+
+const EMAIL_COLUMNS: &[&str] = &["email", "email_address"];
+const CONFIRMED_AT_COLUMNS: &[&str] = &["confirmed_at", "confirm_time", "optin_time"];
+
+struct Row {
+    email: String,
+    confirmed_at: Option<u64>,
+}
+
+/// Parses the CSV file, skipping (and reporting) invalid addresses or dates
+/// and the addresses repeated in the file.
+fn parse_rows(content: &[u8], report: &mut ImportReport) -> Result<Vec<Row>, Error> {
+    let mut reader = csv::Reader::from_reader(content);
+    let headers: Vec<String> = reader
+        .headers()?
+        .iter()
+        .map(|h| h.trim().to_lowercase().replace([' ', '-'], "_"))
+        .collect();
+    let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h.as_str()));
+    let email_column =
+        column(EMAIL_COLUMNS).ok_or_else(|| format!("No email column found in {:?}", headers))?;
+    let confirmed_at_column = column(CONFIRMED_AT_COLUMNS);
+
+    let mut seen = HashSet::new();
+    let mut rows = vec![];
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let value = record.get(email_column).unwrap_or_default();
+        let Some(email) = normalize_email(value) else {
+            report.invalid.push(InvalidRow {
+                line,
+                value: value.to_string(),
+                reason: "Invalid email".to_string(),
+            });
+            continue;
+        };
+        // a missing date falls back to the time of the import
+        let confirmed_at = match confirmed_at_column
+            .and_then(|c| record.get(c))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(parse_timestamp)
+        {
+            None => None,
+            Some(Ok(confirmed_at)) => Some(confirmed_at),
+            Some(Err(err)) => {
+                report.invalid.push(InvalidRow {
+                    line,
+                    value: value.to_string(),
+                    reason: err.to_string(),
+                });
+                continue;
+            }
+        };
+        if !seen.insert(email.clone()) {
+            report.duplicates += 1;
+            continue;
+        }
+        rows.push(Row {
+            email,
+            confirmed_at,
+        });
+    }
+
+    Ok(rows)
+}
+
+async fn existing_emails(campaign_id: &str, config: &Config) -> Result<HashSet<String>, Error> {
+    let items = config
+        .dynamodb_client
+        .query()
+        .table_name(&config.env.subscriptions_table)
+        .key_condition_expression("campaign_id = :campaign_id")
+        .expression_attribute_values(":campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .projection_expression("email")
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(Box::new)?;
+
+    Ok(items
+        .iter()
+        .filter_map(|item| item.get("email").and_then(|e| e.as_s().ok()))
+        .map(|email| normalize_email(email).unwrap_or_else(|| email.to_string()))
+        .collect())
+}
+
+/// The addresses among `emails` that bounced or complained, retrying the
+/// unprocessed keys.
+async fn suppressed_emails(emails: &[String], config: &Config) -> Result<HashSet<String>, Error> {
+    let table = &config.env.suppressions_table;
+    let mut suppressed = HashSet::new();
+    'chunks: for chunk in emails.chunks(BATCH_GET_SIZE) {
+        let mut keys: Vec<_> = chunk
+            .iter()
+            .map(|email| HashMap::from([("email".to_string(), AttributeValue::S(email.clone()))]))
+            .collect();
+        for attempt in 0..MAX_ATTEMPTS {
+            let output = config
+                .dynamodb_client
+                .batch_get_item()
+                .request_items(
+                    table,
+                    KeysAndAttributes::builder()
+                        .set_keys(Some(keys))
+                        .projection_expression("email")
+                        .build()?,
+                )
+                .send()
+                .await
+                .map_err(Box::new)?;
+            let items = output
+                .responses
+                .and_then(|mut responses| responses.remove(table))
+                .unwrap_or_default();
+            suppressed.extend(
+                items
+                    .iter()
+                    .filter_map(|item| item.get("email").and_then(|e| e.as_s().ok()).cloned()),
+            );
+            keys = output
+                .unprocessed_keys
+                .and_then(|mut unprocessed| unprocessed.remove(table))
+                .map(|unprocessed| unprocessed.keys)
+                .unwrap_or_default();
+            if keys.is_empty() {
+                continue 'chunks;
+            }
+            tracing::info!(
+                unprocessed = keys.len(),
+                attempt,
+                "Retrying unprocessed keys"
+            );
+            tokio::time::sleep(backoff(attempt)).await;
+        }
+        return Err(format!(
+            "{} suppressions still unprocessed after {} attempts",
+            keys.len(),
+            MAX_ATTEMPTS
+        )
+        .into());
+    }
+    Ok(suppressed)
+}
+
+/// The confirmation emails of the pending subscriptions of a previous run
+/// of the same import that were never sent, which its failure may have left
+/// unqueued. Queuing them again may send a second email to the few that
+/// were queued but not sent yet, rather than none to the others.
+async fn unsent_confirmations(
+    job: &ImportJob,
+    config: &Config,
+) -> Result<Vec<SubscribeEventPayload>, Error> {
+    let items = config
+        .dynamodb_client
+        .query()
+        .table_name(&config.env.subscriptions_table)
+        .key_condition_expression("campaign_id = :campaign_id")
+        .filter_expression(
+            "#import.s3_key = :s3_key AND attribute_not_exists(sent_at) \
+             AND attribute_not_exists(confirmed_at) AND attribute_not_exists(unsubscribed_at)",
+        )
+        .expression_attribute_names("#import", "import")
+        .expression_attribute_values(":campaign_id", AttributeValue::S(job.campaign_id.clone()))
+        .expression_attribute_values(":s3_key", AttributeValue::S(job.s3_key.clone()))
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(Box::new)?;
+
+    items
+        .into_iter()
+        .map(|item| {
+            let subscription: Subscription = serde_dynamo::from_item(item)?;
+            Ok(confirmation_payload(&subscription))
+        })
+        .collect()
+}
+
+fn confirmation_payload(subscription: &Subscription) -> SubscribeEventPayload {
+    SubscribeEventPayload {
+        subscription_id: subscription.subscription_id.clone(),
+        campaign_id: subscription.campaign_id.clone(),
+        email: subscription.email.clone(),
+        force: false,
+        locale: None,
+    }
+}
+
+/// Writes a batch of subscriptions, retrying the unprocessed items.
+async fn batch_write(mut requests: Vec<WriteRequest>, config: &Config) -> Result<(), Error> {
+    let table = &config.env.subscriptions_table;
+    for attempt in 0..MAX_ATTEMPTS {
+        let output = config
+            .dynamodb_client
+            .batch_write_item()
+            .request_items(table, requests)
+            .send()
+            .await
+            .map_err(Box::new)?;
+        requests = output
+            .unprocessed_items
+            .and_then(|mut unprocessed| unprocessed.remove(table))
+            .unwrap_or_default();
+        if requests.is_empty() {
+            return Ok(());
+        }
+        tracing::info!(
+            unprocessed = requests.len(),
+            attempt,
+            "Retrying unprocessed items"
+        );
+        tokio::time::sleep(backoff(attempt)).await;
+    }
+    Err(format!(
+        "{} subscriptions still unprocessed after {} attempts",
+        requests.len(),
+        MAX_ATTEMPTS
+    )
+    .into())
+}
+
+/// Queues the confirmation emails of some subscriptions, by batches.
+async fn queue_confirmations(
+    payloads: &[SubscribeEventPayload],
+    config: &Config,
+) -> Result<(), Error> {
+    for chunk in payloads.chunks(SEND_MESSAGE_BATCH_SIZE) {
+        let entries = chunk
+            .iter()
+            .enumerate()
+            .map(|(i, payload)| {
+                Ok(SendMessageBatchRequestEntry::builder()
+                    .id(i.to_string())
+                    .message_body(serde_json::to_string(payload)?)
+                    .build()?)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        send_batch(&config.sqs_client, &config.env.email_queue, entries).await?;
+    }
+    Ok(())
+}
+
+async fn function_handler(
+    event: LambdaEvent<ImportJob>,
+    config: &Config,
+) -> Result<ImportReport, Error> {
+    let job = event.payload;
+    tracing::info!("Starting import: {:?}", job);
+
+    let campaign = config
+        .dynamodb_client
+        .get_item()
+        .table_name(&config.env.campaigns_table)
+        .key("campaign_id", AttributeValue::S(job.campaign_id.clone()))
+        .send()
+        .await
+        .map_err(Box::new)?
+        .item
+        .ok_or_else(|| format!("Campaign {} not found", job.campaign_id))?;
+    let campaign: Campaign = serde_dynamo::from_item(campaign)?;
+    if !campaign.is_active() {
+        return Err(format!("Campaign {} is archived", job.campaign_id).into());
+    }
+    if !job.has_valid_s3_key() {
+        return Err(format!(
+            "{} is not a file under {}",
+            job.s3_key,
+            import_prefix(&job.campaign_id)
+        )
+        .into());
+    }
+
+    let content = config
+        .s3_client
+        .get_object()
+        .bucket(&config.env.resources_bucket)
+        .key(&job.s3_key)
+        .send()
+        .await
+        .map_err(Box::new)?
+        .body
+        .collect()
+        .await?
+        .into_bytes();
+
+    let mut report = ImportReport::default();
+    // when the import is retried after failing
+    if job.mode == ImportMode::Confirm {
+        let unsent = unsent_confirmations(&job, config).await?;
+        queue_confirmations(&unsent, config).await?;
+        report.queued += unsent.len();
+    }
+
+    let mut rows = parse_rows(&content, &mut report)?;
+    let existing = existing_emails(&job.campaign_id, config).await?;
+    let parsed = rows.len();
+    rows.retain(|row| !existing.contains(&row.email));
+    report.already_subscribed = parsed - rows.len();
+    // checked before anything is written or queued for them
+    let emails: Vec<_> = rows.iter().map(|row| row.email.clone()).collect();
+    let suppressed = suppressed_emails(&emails, config).await?;
+    rows.retain(|row| !suppressed.contains(&row.email));
+    report.suppressed = suppressed.len();
+
+    let now = unix_now();
+    let pre_confirmed = job.mode == ImportMode::PreConfirmed;
+    let subscriptions: Vec<Subscription> = rows
+        .into_iter()
+        .map(|row| Subscription {
+            campaign_id: job.campaign_id.clone(),
+            subscription_id: cuid::cuid2(),
+            email: row.email,
+            ip: String::new(),
+            locale: None,
+            fields: Default::default(),
+            created_at: Some(now),
+            sent_at: None,
+            ses_message_id: None,
+            send_attempts: None,
+            opened_at: None,
+            confirmed_at: match pre_confirmed {
+                true => Some(row.confirmed_at.unwrap_or(now)),
+                false => None,
+            },
+            delivered_at: None,
+            unsubscribed_at: None,
+            suppressed_at: None,
+            reminders_sent: None,
+            reminded_at: None,
+            // reminded (or expired) from now if the confirmation is never sent
+            pending_sent_at: match pre_confirmed {
+                true => None,
+                false => Some(now),
+            },
+            expires_at: None,
+            drip_steps_sent: None,
+            import: Some(ImportProvenance {
+                source: job.source.clone(),
+                s3_key: job.s3_key.clone(),
+                imported_at: now,
+                pre_confirmed,
+            }),
+        })
+        .collect();
+
+    // the confirmation emails of a chunk are queued as soon as it's written
+    for chunk in subscriptions.chunks(BATCH_WRITE_SIZE) {
+        let requests = chunk
+            .iter()
+            .map(|subscription| {
+                Ok(WriteRequest::builder()
+                    .put_request(
+                        PutRequest::builder()
+                            .set_item(Some(serde_dynamo::to_item(subscription)?))
+                            .build()?,
+                    )
+                    .build())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        batch_write(requests, config).await?;
+        report.imported += chunk.len();
+
+        if job.mode == ImportMode::Confirm {
+            let payloads: Vec<_> = chunk.iter().map(confirmation_payload).collect();
+            queue_confirmations(&payloads, config).await?;
+            report.queued += chunk.len();
+        }
+    }
+
+    config
+        .s3_client
+        .put_object()
+        .bucket(&config.env.resources_bucket)
+        .key(job.report_s3_key())
+        .content_type("application/json")
+        .body(ByteStream::from(serde_json::to_vec_pretty(&report)?))
+        .send()
+        .await
+        .map_err(Box::new)?;
+    tracing::info!(
+        imported = report.imported,
+        already_subscribed = report.already_subscribed,
+        duplicates = report.duplicates,
+        suppressed = report.suppressed,
+        invalid = report.invalid.len(),
+        queued = report.queued,
+        "Import completed"
+    );
+
+    Ok(report)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let env = SamEnv::from_env()?;
+
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = aws_sdk_dynamodb::Client::new(&config);
+    let s3_client = aws_sdk_s3::Client::new(&config);
+    let sqs_client = aws_sdk_sqs::Client::new(&config);
+
+    let config = Config {
+        env,
+        dynamodb_client,
+        s3_client,
+        sqs_client,
+    };
+
+    tracing::init_default_subscriber();
+
+    run(service_fn(|event| function_handler(event, &config))).await
+}
@@ -1,110 +1,954 @@
-use aws_lambda_events::event::sqs::SqsEvent;
-use aws_sdk_ses::types::{Body, Content, Destination, Message};
-use jsonwebtoken::{encode, EncodingKey, Header};
+mod throttle;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use aws_lambda_events::event::sqs::{BatchItemFailure, SqsBatchResponse, SqsEvent, SqsMessage};
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValuesOnConditionCheckFailure};
+use aws_sdk_scheduler::types::{
+    ActionAfterCompletion, FlexibleTimeWindow, FlexibleTimeWindowMode, Target,
+};
+use aws_sdk_sesv2::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_sesv2::operation::send_email::SendEmailError;
+use aws_sdk_sesv2::types::{Body, Content, Destination, EmailContent, Message, MessageTag};
 use lambda_runtime::{run, service_fn, tracing, Error, LambdaEvent};
-use shared::{SubscribeConfirmationTokenClaims, SubscribeEventPayload};
-use std::env;
-include!(concat!(env!("OUT_DIR"), "/sam_env.rs"));
+use sam_env_macros::sam_env;
+use shared::broadcast::{Broadcast, BroadcastDelivery, DeliveryStatus};
+use shared::campaign::{Campaign, DripStep};
+use shared::email::format_address;
+use shared::export::format_timestamp;
+use shared::fields::field_variables;
+use shared::i18n::Text;
+use shared::render::{render, render_text};
+use shared::sqs::{retry, MAX_ATTEMPTS};
+use shared::subscription::{Subscription, SubscriptionStatus};
+use shared::suppression::Suppression;
+use shared::{
+    keyring::Keyring, unix_now, BroadcastEmail, DripEmail, EmailJob,
+    SubscribeConfirmationTokenClaims, SubscribeEventPayload, UnsubscribeTokenClaims,
+};
+use throttle::{acquire_token, backoff, Throttled};
+
+#[sam_env(template = "../../template.yaml")]
+struct SamEnv;
 
 struct Config {
     env: SamEnv,
     dynamodb_client: aws_sdk_dynamodb::Client,
-    ses_client: aws_sdk_ses::Client,
-    token_secret: EncodingKey,
+    s3_client: aws_sdk_s3::Client,
+    ses_client: aws_sdk_sesv2::Client,
+    sqs_client: aws_sdk_sqs::Client,
+    scheduler_client: aws_sdk_scheduler::Client,
+    keyring: Keyring,
+    /// The templates already downloaded, by S3 key
+    templates: Mutex<HashMap<String, String>>,
 }
 
-async fn function_handler(event: LambdaEvent<SqsEvent>, config: &Config) -> Result<(), Error> {
-    // TODO: validate campaign id and subscription id
-    // TODO: get the template from database
-    // TODO: tracking pixel
-    // TODO: implement list of failed messages
-
-    // Extract some useful information from the request
-    for record in event.payload.records {
-        if let Some(sqs_body) = record.body {
-            // generate unique token (needs to have campaign id and subscription id)
-            let sqs_message: SubscribeEventPayload = serde_json::from_str(&sqs_body)?;
-            tracing::info!("Received message: {:?}", sqs_message);
-
-            let confirmation_token_claims = SubscribeConfirmationTokenClaims::new(
-                sqs_message.subscription_id.clone(),
-                sqs_message.campaign_id.clone(),
-                sqs_message.email.clone(),
-                60 * 60 * 24,
+// the longest delay SQS supports
+pub(crate) const MAX_DELAY_SECONDS: u64 = 900;
+
+/// An email to a subscriber.
+struct OutgoingEmail<'a> {
+    /// The SQS message it's sent for (or the broadcast delivery or drip
+    /// step), so that it's sent once
+    message_id: &'a str,
+    to: &'a str,
+    /// Who it's sent by and the replies go to
+    campaign: &'a Campaign,
+    subscription_id: &'a str,
+    /// Tagged on the message: confirmation, reminder, broadcast or drip
+    email_type: &'static str,
+    subject: &'a str,
+    text: String,
+    html: String,
+}
+
+enum SendOutcome {
+    /// With the SES message id
+    Sent(String),
+    Suppressed,
+    /// Sent for a previous delivery of the SQS message, with the SES message
+    /// id
+    AlreadySent(String),
+    /// Refused by SES for good, with the reason
+    Rejected(String),
+}
+
+/// Whether a failed SES call is worth retrying: throttling, paused sending
+/// and errors of the service or of the network are, while a message SES
+/// refuses (e.g. an invalid address) would fail the same way every time.
+fn is_permanent(err: &SdkError<SendEmailError>) -> bool {
+    matches!(
+        err.as_service_error(),
+        Some(SendEmailError::MessageRejected(_) | SendEmailError::BadRequestException(_))
+    )
+}
+
+fn is_throttling(err: &SdkError<SendEmailError>) -> bool {
+    match err.as_service_error() {
+        Some(SendEmailError::TooManyRequestsException(_)) => true,
+        Some(err) => err.code() == Some("Throttling"),
+        None => false,
+    }
+}
+
+/// How long a claim on a message lasts before a redelivery can take it
+/// over: longer than the function can run (120s), and shorter than the
+/// visibility timeout of the queue (240s), so that the message of a crashed
+/// invocation is sent when it comes back.
+const CLAIM_TIMEOUT_SECONDS: u64 = 150;
+
+enum Claim {
+    /// The email can be sent
+    Claimed,
+    /// A previous delivery of the message sent it, with the SES message id
+    Sent(String),
+    /// A previous delivery of the message is still sending it
+    InProgress,
+}
+
+/// Records that the email of a message is being sent, unless it was sent
+/// already or a previous delivery of the message is still sending it.
+async fn claim(email: &OutgoingEmail<'_>, config: &Config) -> Result<Claim, Error> {
+    let now = unix_now();
+    let result = config
+        .dynamodb_client
+        .put_item()
+        .table_name(&config.env.sent_emails_table)
+        .item(
+            "message_id",
+            AttributeValue::S(email.message_id.to_string()),
+        )
+        .item(
+            "subscription_id",
+            AttributeValue::S(email.subscription_id.to_string()),
+        )
+        .item("status", AttributeValue::S("sending".to_string()))
+        .item("claimed_at", AttributeValue::N(now.to_string()))
+        .item(
+            "expires_at",
+            AttributeValue::N((now + config.env.sent_emails_ttl.as_secs()).to_string()),
+        )
+        .condition_expression(
+            "attribute_not_exists(message_id) OR (#status = :sending AND claimed_at < :stale)",
+        )
+        .expression_attribute_names("#status", "status")
+        .expression_attribute_values(":sending", AttributeValue::S("sending".to_string()))
+        .expression_attribute_values(
+            ":stale",
+            AttributeValue::N(now.saturating_sub(CLAIM_TIMEOUT_SECONDS).to_string()),
+        )
+        .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+        .send()
+        .await;
+    let err = match result {
+        Ok(_) => return Ok(Claim::Claimed),
+        Err(err) => err.into_service_error(),
+    };
+    let PutItemError::ConditionalCheckFailedException(failed) = err else {
+        return Err(Box::new(err).into());
+    };
+    let claimed = failed.item.unwrap_or_default();
+    let attribute = |name: &str| match claimed.get(name) {
+        Some(AttributeValue::S(value)) => value.clone(),
+        _ => String::new(),
+    };
+    Ok(match attribute("status").as_str() {
+        "sent" => Claim::Sent(attribute("ses_message_id")),
+        _ => Claim::InProgress,
+    })
+}
+
+/// Releases the claim on a message that couldn't be sent, which lets the
+/// next delivery of the message try again.
+async fn release(message_id: &AttributeValue, config: &Config) -> Result<(), Error> {
+    config
+        .dynamodb_client
+        .delete_item()
+        .table_name(&config.env.sent_emails_table)
+        .key("message_id", message_id.clone())
+        .send()
+        .await
+        .map_err(Box::new)?;
+    Ok(())
+}
+
+/// Sends an email, unless the address is in the suppression list or it was
+/// sent for the same SQS message already. The message is tagged with the
+/// campaign, the subscription and the type of email, so that the SES
+/// notifications can be traced back to them.
+///
+/// The sending rate is kept under `MAX_SEND_RATE`, failing with `Throttled`
+/// when it can't be, and so are the throttling errors of SES (which the SDK
+/// already retried a few times).
+async fn send_email(email: OutgoingEmail<'_>, config: &Config) -> Result<SendOutcome, Error> {
+    let suppressed = config
+        .dynamodb_client
+        .get_item()
+        .table_name(&config.env.suppressions_table)
+        .key("email", AttributeValue::S(email.to.to_string()))
+        .send()
+        .await
+        .map_err(Box::new)?
+        .item
+        .map(serde_dynamo::from_item::<_, Suppression>)
+        .transpose()?;
+    if let Some(suppression) = suppressed {
+        tracing::info!("Not sending to a suppressed address: {:?}", suppression);
+        return Ok(SendOutcome::Suppressed);
+    }
+    // claimed first, so that a message sent already doesn't take a token
+    match claim(&email, config).await? {
+        Claim::Claimed => {}
+        Claim::Sent(ses_message_id) => {
+            tracing::info!(
+                message_id = email.message_id,
+                "Email already sent for this message"
             );
-            let confirmation_token_token = encode(
-                &Header::default(),
-                &confirmation_token_claims,
-                &config.token_secret,
-            )?;
-            tracing::info!("Confirmation token: {}", confirmation_token_token);
-
-            let confirmation_url = format!(
-                "{}?token={}",
-                config.env.confirmation_endpoint, confirmation_token_token
+            return Ok(SendOutcome::AlreadySent(ses_message_id));
+        }
+        // retried, in case the other delivery fails
+        Claim::InProgress => {
+            return Err(format!(
+                "Email of message {} still being sent by a previous delivery",
+                email.message_id
+            )
+            .into())
+        }
+    }
+    let message_id = AttributeValue::S(email.message_id.to_string());
+    if let Err(err) = acquire_token(config).await {
+        release(&message_id, config).await?;
+        return Err(err);
+    }
+
+    let subject = Content::builder().data(email.subject).build()?;
+    let email_body = Body::builder()
+        .text(Content::builder().data(email.text).build()?)
+        .html(Content::builder().data(email.html).build()?)
+        .build();
+    let content = EmailContent::builder()
+        .simple(Message::builder().subject(subject).body(email_body).build())
+        .build();
+
+    let mut request = config
+        .ses_client
+        .send_email()
+        .from_email_address(format_address(
+            email.campaign.from_name.as_deref(),
+            email
+                .campaign
+                .sender_email
+                .as_deref()
+                .unwrap_or(&config.env.sender_email),
+        ))
+        .destination(Destination::builder().to_addresses(email.to).build())
+        .content(content)
+        .configuration_set_name(&config.env.ses_configuration_set);
+    if let Some(reply_to) = &email.campaign.reply_to {
+        request = request.reply_to_addresses(reply_to);
+    }
+    for (name, value) in [
+        ("campaign_id", email.campaign.campaign_id.as_str()),
+        ("subscription_id", email.subscription_id),
+        ("email_type", email.email_type),
+    ] {
+        request = request.email_tags(MessageTag::builder().name(name).value(value).build()?);
+    }
+    let send_result = request.send().await;
+
+    let send_result = match send_result {
+        Ok(send_result) => send_result,
+        Err(err) => {
+            release(&message_id, config).await?;
+            if is_permanent(&err) {
+                let reason = err
+                    .as_service_error()
+                    .and_then(|err| err.message())
+                    .unwrap_or("rejected")
+                    .to_string();
+                return Ok(SendOutcome::Rejected(reason));
+            }
+            if is_throttling(&err) {
+                tracing::warn!("Throttled by SES: {}", err);
+                return Err(Throttled.into());
+            }
+            return Err(Box::new(err).into());
+        }
+    };
+    tracing::info!("Email sent: {:?}", send_result);
+    let ses_message_id = send_result.message_id.unwrap_or_default();
+
+    // the email is sent whether or not this is recorded: failing the message
+    // would have its redelivery take the claim over once it times out, and
+    // send the email again
+    let mark_sent = config
+        .dynamodb_client
+        .update_item()
+        .table_name(&config.env.sent_emails_table)
+        .key("message_id", message_id)
+        .update_expression("SET #status = :sent, ses_message_id = :ses_message_id")
+        .expression_attribute_names("#status", "status")
+        .expression_attribute_values(":sent", AttributeValue::S("sent".to_string()))
+        .expression_attribute_values(":ses_message_id", AttributeValue::S(ses_message_id.clone()));
+    if let Err(err) = retry(MAX_ATTEMPTS, || mark_sent.clone().send()).await {
+        tracing::error!(
+            message_id = email.message_id,
+            ses_message_id,
+            "Email sent but not recorded as sent: {}",
+            err
+        );
+    }
+
+    Ok(SendOutcome::Sent(ses_message_id))
+}
+
+/// The campaign and the subscription to send the confirmation email for, or
+/// why it shouldn't be sent.
+async fn confirmation_subscription(
+    sqs_message: &SubscribeEventPayload,
+    config: &Config,
+) -> Result<Result<(Campaign, Subscription), &'static str>, Error> {
+    let campaign: Option<Campaign> = get_item(
+        &config.env.campaigns_table,
+        &[("campaign_id", &sqs_message.campaign_id)],
+        config,
+    )
+    .await?;
+    let campaign = match campaign {
+        None => return Ok(Err("campaign not found")),
+        Some(campaign) if !campaign.is_active() => return Ok(Err("campaign archived")),
+        Some(campaign) => campaign,
+    };
+
+    let subscription: Option<Subscription> = get_item(
+        &config.env.subscriptions_table,
+        &[
+            ("campaign_id", &sqs_message.campaign_id),
+            ("subscription_id", &sqs_message.subscription_id),
+        ],
+        config,
+    )
+    .await?;
+    Ok(match subscription {
+        None => Err("subscription not found"),
+        Some(subscription) if subscription.suppressed_at.is_some() => Err("address suppressed"),
+        // forcing only sends to the confirmed subscriptions again
+        Some(subscription) => match subscription.status() {
+            SubscriptionStatus::Pending => Ok((campaign, subscription)),
+            SubscriptionStatus::Confirmed if sqs_message.force => Ok((campaign, subscription)),
+            SubscriptionStatus::Confirmed => Err("already confirmed"),
+            SubscriptionStatus::Unsubscribed => Err("unsubscribed"),
+        },
+    })
+}
+
+async fn send_confirmation(
+    message_id: &str,
+    sqs_message: SubscribeEventPayload,
+    reminder: bool,
+    config: &Config,
+) -> Result<(), Error> {
+    // the message may be a replay, or the subscription may have changed
+    // since it was queued
+    let (campaign, subscription) = match confirmation_subscription(&sqs_message, config).await? {
+        Ok(found) => found,
+        Err(reason) => {
+            tracing::info!(reason, "Dropping confirmation email: {:?}", sqs_message);
+            return Ok(());
+        }
+    };
+
+    // generate unique token (needs to have campaign id and subscription id)
+    let confirmation_token_claims = SubscribeConfirmationTokenClaims::new(
+        sqs_message.subscription_id.clone(),
+        sqs_message.campaign_id.clone(),
+        subscription.email.clone(),
+        subscription.locale.clone(),
+        config.env.confirmation_token_ttl.as_secs(),
+    );
+    let confirmation_token_token = config.keyring.encode(&confirmation_token_claims)?;
+    tracing::info!("Confirmation token: {}", confirmation_token_token);
+
+    let mut confirmation_url = config.env.confirmation_endpoint.clone();
+    confirmation_url
+        .query_pairs_mut()
+        .append_pair("token", &confirmation_token_token);
+    tracing::info!("Confirmation url: {}", confirmation_url);
+
+    // in the locale the subscriber chose, if the campaign still has it
+    let requested = subscription
+        .locale
+        .as_deref()
+        .or(sqs_message.locale.as_deref());
+    let locale = campaign.negotiate_locale(requested, None);
+    let subject = match reminder {
+        true => campaign.text(&locale, Text::ReminderSubject),
+        false => campaign.text(&locale, Text::ConfirmationSubject),
+    };
+    let fields = field_variables(&campaign.fields, &subscription.fields);
+    let mut variables = vec![
+        ("confirmation_url", confirmation_url.as_str()),
+        ("email", subscription.email.as_str()),
+        ("campaign_name", &campaign.name),
+        ("locale", &locale),
+    ];
+    variables.extend(
+        fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    );
+    // the uploaded template replaces the (translated) text of the campaign
+    let html_template = match campaign.email_template_s3_key.as_str() {
+        "" => campaign.text(&locale, Text::ConfirmationHtml).to_string(),
+        s3_key => load_template(s3_key, config).await?,
+    };
+    let email = OutgoingEmail {
+        message_id,
+        to: &subscription.email,
+        campaign: &campaign,
+        subscription_id: &sqs_message.subscription_id,
+        email_type: match reminder {
+            true => "reminder",
+            false => "confirmation",
+        },
+        subject,
+        text: render_text(campaign.text(&locale, Text::ConfirmationText), &variables),
+        html: render(&html_template, &variables),
+    };
+    // a previous delivery of the message may have failed after sending it
+    let ses_message_id = match send_email(email, config).await? {
+        SendOutcome::Sent(ses_message_id) | SendOutcome::AlreadySent(ses_message_id) => {
+            ses_message_id
+        }
+        SendOutcome::Rejected(reason) => {
+            tracing::warn!(reason, "Confirmation email rejected: {:?}", sqs_message);
+            return Ok(());
+        }
+        SendOutcome::Suppressed => return Ok(()),
+    };
+
+    // the message id of the last email, to trace the SES notifications back
+    // to the subscription. A pending one waits for a reminder from now
+    let mut update = "SET sent_at = :now, ses_message_id = :ses_message_id".to_string();
+    if subscription.status() == SubscriptionStatus::Pending {
+        update.push_str(", pending_sent_at = :now");
+    }
+    update.push_str(" ADD send_attempts :one");
+    let result = config
+        .dynamodb_client
+        .update_item()
+        .table_name(&config.env.subscriptions_table)
+        .key(
+            "campaign_id",
+            AttributeValue::S(sqs_message.campaign_id.clone()),
+        )
+        .key(
+            "subscription_id",
+            AttributeValue::S(sqs_message.subscription_id.clone()),
+        )
+        .update_expression(update)
+        .condition_expression(
+            "attribute_exists(subscription_id) AND \
+             (attribute_not_exists(ses_message_id) OR ses_message_id <> :ses_message_id)",
+        )
+        .expression_attribute_values(":now", AttributeValue::N(unix_now().to_string()))
+        .expression_attribute_values(":ses_message_id", AttributeValue::S(ses_message_id))
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .send()
+        .await;
+    match result {
+        Ok(_) => {}
+        // deleted in the meantime, or recorded by a previous delivery
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) => {}
+        Err(err) => return Err(Box::new(err).into()),
+    }
+
+    Ok(())
+}
+
+async fn get_item<T: serde::de::DeserializeOwned>(
+    table: &str,
+    key: &[(&str, &str)],
+    config: &Config,
+) -> Result<Option<T>, Error> {
+    let mut request = config.dynamodb_client.get_item().table_name(table);
+    for (name, value) in key {
+        request = request.key(*name, AttributeValue::S(value.to_string()));
+    }
+    Ok(match request.send().await.map_err(Box::new)?.item {
+        Some(item) => Some(serde_dynamo::from_item(item)?),
+        None => None,
+    })
+}
+
+async fn load_template(s3_key: &str, config: &Config) -> Result<String, Error> {
+    if let Some(template) = config.templates.lock().unwrap().get(s3_key) {
+        return Ok(template.clone());
+    }
+    let content = config
+        .s3_client
+        .get_object()
+        .bucket(&config.env.resources_bucket)
+        .key(s3_key)
+        .send()
+        .await
+        .map_err(Box::new)?
+        .body
+        .collect()
+        .await?
+        .into_bytes();
+    let template = String::from_utf8(content.to_vec())?;
+    config
+        .templates
+        .lock()
+        .unwrap()
+        .insert(s3_key.to_string(), template.clone());
+    Ok(template)
+}
+
+async fn load_campaign(campaign_id: &str, config: &Config) -> Result<Campaign, Error> {
+    get_item(
+        &config.env.campaigns_table,
+        &[("campaign_id", campaign_id)],
+        config,
+    )
+    .await?
+    .ok_or_else(|| format!("Campaign {} not found", campaign_id).into())
+}
+
+/// Renders the text and HTML bodies of an email sent to a subscriber, with
+/// a link to unsubscribe and the values of the fields of the form.
+async fn render_email(
+    template_s3_key: &str,
+    campaign: &Campaign,
+    subscription: &Subscription,
+    config: &Config,
+) -> Result<(String, String), Error> {
+    let template = load_template(template_s3_key, config).await?;
+    let subscription_id = &subscription.subscription_id;
+
+    let unsubscribe_token = config.keyring.encode(&UnsubscribeTokenClaims::new(
+        subscription_id.to_string(),
+        campaign.campaign_id.clone(),
+        config.env.unsubscribe_token_ttl.as_secs(),
+    ))?;
+    let mut unsubscribe_url = config
+        .env
+        .unsubscribe_endpoint
+        .join(&format!("{}/unsubscribe", subscription_id))?;
+    unsubscribe_url
+        .query_pairs_mut()
+        .append_pair("token", &unsubscribe_token);
+
+    let fields = field_variables(&campaign.fields, &subscription.fields);
+    let mut variables = vec![
+        ("email", subscription.email.as_str()),
+        ("campaign_name", &campaign.name),
+        ("unsubscribe_url", unsubscribe_url.as_str()),
+    ];
+    variables.extend(
+        fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    );
+    let html = render(&template, &variables);
+    let text = format!(
+        "This email is best viewed in an email client that supports HTML.\n\nUnsubscribe: {}",
+        unsubscribe_url
+    );
+    Ok((text, html))
+}
+
+async fn update_delivery(
+    email: &BroadcastEmail,
+    status: DeliveryStatus,
+    ses_message_id: Option<String>,
+    error: Option<String>,
+    config: &Config,
+) -> Result<(), Error> {
+    let mut request = config
+        .dynamodb_client
+        .update_item()
+        .table_name(&config.env.broadcast_deliveries_table)
+        .key(
+            "broadcast_id",
+            AttributeValue::S(email.broadcast_id.clone()),
+        )
+        .key(
+            "subscription_id",
+            AttributeValue::S(email.subscription_id.clone()),
+        )
+        .expression_attribute_names("#status", "status")
+        .expression_attribute_values(
+            ":status",
+            AttributeValue::S(
+                serde_json::to_value(status)?
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+        );
+    let mut update = "SET #status = :status".to_string();
+    if let Some(ses_message_id) = ses_message_id {
+        update.push_str(", sent_at = :now, ses_message_id = :ses_message_id");
+        request = request
+            .expression_attribute_values(":now", AttributeValue::N(unix_now().to_string()))
+            .expression_attribute_values(":ses_message_id", AttributeValue::S(ses_message_id));
+    }
+    if let Some(error) = error {
+        update.push_str(", #error = :error");
+        request = request
+            .expression_attribute_names("#error", "error")
+            .expression_attribute_values(":error", AttributeValue::S(error));
+    }
+    request
+        .update_expression(update)
+        .send()
+        .await
+        .map_err(Box::new)?;
+    Ok(())
+}
+
+/// Sends a broadcast to a subscriber, unless it was already sent or they
+/// are not receiving broadcasts anymore.
+async fn send_broadcast(email: BroadcastEmail, config: &Config) -> Result<(), Error> {
+    let delivery: Option<BroadcastDelivery> = get_item(
+        &config.env.broadcast_deliveries_table,
+        &[
+            ("broadcast_id", &email.broadcast_id),
+            ("subscription_id", &email.subscription_id),
+        ],
+        config,
+    )
+    .await?;
+    if delivery.map(|d| d.status) != Some(DeliveryStatus::Queued) {
+        tracing::info!("Broadcast already handled, skipping: {:?}", email);
+        return Ok(());
+    }
+
+    let subscription: Option<Subscription> = get_item(
+        &config.env.subscriptions_table,
+        &[
+            ("campaign_id", &email.campaign_id),
+            ("subscription_id", &email.subscription_id),
+        ],
+        config,
+    )
+    .await?;
+    let Some(subscription) = subscription.filter(|s| s.receives_broadcasts()) else {
+        tracing::info!("Subscriber not receiving broadcasts anymore: {:?}", email);
+        return update_delivery(&email, DeliveryStatus::Skipped, None, None, config).await;
+    };
+
+    let broadcast: Broadcast = get_item(
+        &config.env.broadcasts_table,
+        &[
+            ("campaign_id", &email.campaign_id),
+            ("broadcast_id", &email.broadcast_id),
+        ],
+        config,
+    )
+    .await?
+    .ok_or_else(|| format!("Broadcast {} not found", email.broadcast_id))?;
+    let campaign = load_campaign(&email.campaign_id, config).await?;
+    let (text, html) =
+        render_email(&broadcast.template_s3_key, &campaign, &subscription, config).await?;
+
+    // a page of the fan out processed again queues its emails again, while
+    // they're sent once
+    let delivery_id = format!("broadcast#{}#{}", email.broadcast_id, email.subscription_id);
+    let outgoing = OutgoingEmail {
+        message_id: &delivery_id,
+        to: &subscription.email,
+        campaign: &campaign,
+        subscription_id: &email.subscription_id,
+        email_type: "broadcast",
+        subject: &broadcast.subject,
+        text,
+        html,
+    };
+    // the delivery stays queued when sending fails but can be retried
+    match send_email(outgoing, config).await? {
+        SendOutcome::Sent(ses_message_id) | SendOutcome::AlreadySent(ses_message_id) => {
+            update_delivery(
+                &email,
+                DeliveryStatus::Sent,
+                Some(ses_message_id),
+                None,
+                config,
+            )
+            .await
+        }
+        SendOutcome::Suppressed => {
+            update_delivery(&email, DeliveryStatus::Skipped, None, None, config).await
+        }
+        SendOutcome::Rejected(reason) => {
+            update_delivery(&email, DeliveryStatus::Failed, None, Some(reason), config).await
+        }
+    }
+}
+
+/// Queues a step of the drip sequence to be sent at `due`: through a one-off
+/// schedule when it's far enough, straight to the queue otherwise.
+async fn schedule_drip(email: &DripEmail, due: u64, config: &Config) -> Result<(), Error> {
+    let message = serde_json::to_string(&EmailJob::Drip(email.clone()))?;
+    let delay = due.saturating_sub(unix_now());
+    if delay <= MAX_DELAY_SECONDS {
+        config
+            .sqs_client
+            .send_message()
+            .queue_url(&config.env.email_queue)
+            .message_body(message)
+            .delay_seconds(delay as i32)
+            .send()
+            .await
+            .map_err(Box::new)?;
+        return Ok(());
+    }
+
+    let result = config
+        .scheduler_client
+        .create_schedule()
+        .name(format!("{}-{}", email.subscription_id, email.step))
+        .group_name(&config.env.drip_schedule_group)
+        .schedule_expression(format!(
+            "at({})",
+            format_timestamp(due).trim_end_matches('Z')
+        ))
+        .schedule_expression_timezone("UTC")
+        .flexible_time_window(
+            FlexibleTimeWindow::builder()
+                .mode(FlexibleTimeWindowMode::Off)
+                .build()?,
+        )
+        .target(
+            Target::builder()
+                .arn(&config.env.email_queue_arn)
+                .role_arn(&config.env.drip_scheduler_role)
+                .input(message)
+                .build()?,
+        )
+        .action_after_completion(ActionAfterCompletion::Delete)
+        .send()
+        .await;
+    match result {
+        Ok(_) => {
+            tracing::info!(due, "Scheduled drip email: {:?}", email);
+            Ok(())
+        }
+        // scheduled already, by a previous attempt
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conflict_exception())
+                .unwrap_or(false) =>
+        {
+            Ok(())
+        }
+        Err(err) => Err(Box::new(err).into()),
+    }
+}
+
+/// Sends a step of the drip sequence when it's due (scheduling it
+/// otherwise), then schedules the next one. The sequence stops when the
+/// subscriber unsubscribes or gets suppressed, or the campaign is archived.
+async fn send_drip(email: DripEmail, config: &Config) -> Result<(), Error> {
+    let subscription: Option<Subscription> = get_item(
+        &config.env.subscriptions_table,
+        &[
+            ("campaign_id", &email.campaign_id),
+            ("subscription_id", &email.subscription_id),
+        ],
+        config,
+    )
+    .await?;
+    let Some(subscription) = subscription.filter(|s| s.receives_broadcasts()) else {
+        tracing::info!("Subscriber not receiving emails anymore: {:?}", email);
+        return Ok(());
+    };
+    if subscription.drip_steps_sent.unwrap_or_default() as usize != email.step {
+        tracing::info!("Drip email already handled, skipping: {:?}", email);
+        return Ok(());
+    }
+    let campaign = load_campaign(&email.campaign_id, config).await?;
+    if !campaign.is_active() {
+        tracing::info!("Campaign archived, stopping the drip: {:?}", email);
+        return Ok(());
+    }
+    // the sequence may have been shortened in the meantime
+    let Some(step) = campaign.drip.get(email.step) else {
+        return Ok(());
+    };
+
+    let confirmed_at = subscription.confirmed_at.unwrap_or_default();
+    let due_at = |step: &DripStep| confirmed_at + step.delay().unwrap_or_default().as_secs();
+    if due_at(step) > unix_now() {
+        return schedule_drip(&email, due_at(step), config).await;
+    }
+
+    let (text, html) =
+        render_email(&step.template_s3_key, &campaign, &subscription, config).await?;
+    // a step can be queued more than once (e.g. when the confirmation is
+    // clicked again), while it's sent once
+    let step_id = format!("drip#{}#{}", email.subscription_id, email.step);
+    let outgoing = OutgoingEmail {
+        message_id: &step_id,
+        to: &subscription.email,
+        campaign: &campaign,
+        subscription_id: &email.subscription_id,
+        email_type: "drip",
+        subject: &step.subject,
+        text,
+        html,
+    };
+    // when a previous delivery of the message sent it, the sequence goes on
+    match send_email(outgoing, config).await? {
+        SendOutcome::Sent(_) | SendOutcome::AlreadySent(_) => {}
+        SendOutcome::Suppressed => return Ok(()),
+        SendOutcome::Rejected(reason) => {
+            tracing::warn!(
+                reason,
+                "Drip email rejected, stopping the drip: {:?}",
+                email
             );
-            tracing::info!("Confirmation url: {}", confirmation_url);
-
-            // Generate the email content
-            let subject = Content::builder()
-                .data("Please confirm your subscription".to_string())
-                .build()?;
-
-            let message_text = Content::builder()
-                .data(format!(
-                    "Click here to confirm your subscription: {}",
-                    confirmation_url
-                ))
-                .build()?;
-
-            let message_html = Content::builder()
-                .data(format!(
-                    "Click <a href=\"{}\">here</a> to confirm your subscription",
-                    confirmation_url
-                ))
-                .build()?;
-
-            let email_body = Body::builder()
-                .text(message_text)
-                .html(message_html)
-                .build();
-
-            let send_result = config
-                .ses_client
-                .send_email()
-                .source(&config.env.sender_email)
-                .destination(
-                    Destination::builder()
-                        .to_addresses(&sqs_message.email)
-                        .build(),
-                )
-                .message(Message::builder().subject(subject).body(email_body).build())
-                .send()
-                .await?;
-
-            tracing::info!("Email sent: {:?}", send_result);
-
-            // TODO: update the send at timestamp in the subscription record
+            return Ok(());
         }
     }
 
+    config
+        .dynamodb_client
+        .update_item()
+        .table_name(&config.env.subscriptions_table)
+        .key("campaign_id", AttributeValue::S(email.campaign_id.clone()))
+        .key(
+            "subscription_id",
+            AttributeValue::S(email.subscription_id.clone()),
+        )
+        .update_expression("SET drip_steps_sent = :sent")
+        .expression_attribute_values(":sent", AttributeValue::N((email.step + 1).to_string()))
+        .send()
+        .await
+        .map_err(Box::new)?;
+
+    if let Some(next_step) = campaign.drip.get(email.step + 1) {
+        let next = DripEmail {
+            step: email.step + 1,
+            ..email
+        };
+        schedule_drip(&next, due_at(next_step), config).await?;
+    }
+
     Ok(())
 }
 
+async fn handle_record(record: &SqsMessage, config: &Config) -> Result<(), Error> {
+    let (Some(message_id), Some(sqs_body)) = (&record.message_id, &record.body) else {
+        return Ok(());
+    };
+    // retrying a message that can't be parsed wouldn't help
+    let job = match EmailJob::from_json(sqs_body) {
+        Ok(job) => job,
+        Err(err) => {
+            tracing::error!("Dropping invalid message {}: {}", message_id, err);
+            return Ok(());
+        }
+    };
+    tracing::info!("Received message: {:?}", job);
+
+    match job {
+        EmailJob::Confirmation(payload) => {
+            send_confirmation(message_id, payload, false, config).await
+        }
+        EmailJob::Reminder(payload) => send_confirmation(message_id, payload, true, config).await,
+        EmailJob::Broadcast(email) => send_broadcast(email, config).await,
+        EmailJob::Drip(email) => send_drip(email, config).await,
+    }
+}
+
+/// Makes a failed message come back after an exponential backoff, rather
+/// than after the visibility timeout of the queue.
+async fn delay_retry(record: &SqsMessage, config: &Config) -> Result<(), Error> {
+    let receive_count = record
+        .attributes
+        .get("ApproximateReceiveCount")
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(1);
+    config
+        .sqs_client
+        .change_message_visibility()
+        .queue_url(&config.env.email_queue)
+        .receipt_handle(record.receipt_handle.clone().unwrap_or_default())
+        .visibility_timeout(backoff(receive_count).as_secs() as i32)
+        .send()
+        .await
+        .map_err(Box::new)?;
+    Ok(())
+}
+
+async fn function_handler(
+    event: LambdaEvent<SqsEvent>,
+    config: &Config,
+) -> Result<SqsBatchResponse, Error> {
+    // TODO: tracking pixel
+
+    // the records are sent concurrently, within the sending rate
+    let records = event.payload.records;
+    let results =
+        futures::future::join_all(records.iter().map(|record| handle_record(record, config))).await;
+
+    // the failed records are retried, the others are deleted from the queue
+    let mut response = SqsBatchResponse::default();
+    for (record, result) in records.iter().zip(results) {
+        let Err(err) = result else {
+            continue;
+        };
+        match err.downcast_ref::<Throttled>() {
+            Some(_) => tracing::warn!("Throttled, retrying {:?} later", record.message_id),
+            None => tracing::error!("Failed to handle {:?}: {}", record.message_id, err),
+        }
+        if let Err(err) = delay_retry(record, config).await {
+            tracing::error!(
+                "Failed to delay the retry of {:?}: {}",
+                record.message_id,
+                err
+            );
+        }
+        response.batch_item_failures.push(BatchItemFailure {
+            item_identifier: record.message_id.clone().unwrap_or_default(),
+        });
+    }
+
+    Ok(response)
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let env = SamEnv::init_from_env().unwrap();
+    let env = SamEnv::from_env()?;
 
     let config = aws_config::load_from_env().await;
     let dynamodb_client = aws_sdk_dynamodb::Client::new(&config);
-    let ses_client = aws_sdk_ses::Client::new(&config);
-    let token_secret = EncodingKey::from_secret(env.token_secret.as_ref());
+    let s3_client = aws_sdk_s3::Client::new(&config);
+    let ses_client = aws_sdk_sesv2::Client::new(&config);
+    let sqs_client = aws_sdk_sqs::Client::new(&config);
+    let scheduler_client = aws_sdk_scheduler::Client::new(&config);
+    let env = env.resolve_secrets(&config).await?;
+    let keyring = Keyring::from_json(&env.token_keyring)?;
 
     let config = Config {
         env,
         dynamodb_client,
+        s3_client,
         ses_client,
-        token_secret,
+        sqs_client,
+        scheduler_client,
+        keyring,
+        templates: Mutex::new(HashMap::new()),
     };
 
     tracing::init_default_subscriber();
@@ -0,0 +1,76 @@
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_runtime::Error;
+
+use crate::{Config, MAX_DELAY_SECONDS};
+
+/// How long a send waits for a token before giving up and letting the
+/// message come back later.
+const MAX_WAIT_SECONDS: u32 = 5;
+
+/// The first retry of a failed message, doubled at every receive.
+const BASE_BACKOFF_SECONDS: u64 = 30;
+
+/// No token could be taken (or SES throttled the call): the message should
+/// be retried later.
+#[derive(Debug)]
+pub struct Throttled;
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sending rate exceeded")
+    }
+}
+
+impl std::error::Error for Throttled {}
+
+/// Takes one of the `MAX_SEND_RATE` tokens of the current second, shared by
+/// all the concurrent invocations through the send rate table. When they're
+/// all taken it waits for the next second, up to `MAX_WAIT_SECONDS`.
+pub async fn acquire_token(config: &Config) -> Result<(), Error> {
+    for _ in 0..MAX_WAIT_SECONDS {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+        let second = now.as_secs();
+        let result = config
+            .dynamodb_client
+            .update_item()
+            .table_name(&config.env.send_rate_table)
+            .key("window_id", AttributeValue::S(format!("ses#{}", second)))
+            .update_expression("SET expires_at = :expires_at ADD #sent :one")
+            .condition_expression("attribute_not_exists(#sent) OR #sent < :rate")
+            .expression_attribute_names("#sent", "sent")
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(
+                ":rate",
+                AttributeValue::N(config.env.max_send_rate.to_string()),
+            )
+            .expression_attribute_values(
+                ":expires_at",
+                AttributeValue::N((second + 60).to_string()),
+            )
+            .send()
+            .await;
+        match result {
+            Ok(_) => return Ok(()),
+            Err(err)
+                if err
+                    .as_service_error()
+                    .map(|e| e.is_conditional_check_failed_exception())
+                    .unwrap_or(false) =>
+            {
+                tokio::time::sleep(Duration::from_millis(1000 - now.subsec_millis() as u64)).await;
+            }
+            Err(err) => return Err(Box::new(err).into()),
+        }
+    }
+    Err(Throttled.into())
+}
+
+/// How long a failed message stays invisible before its next attempt, given
+/// how many times it was received.
+pub fn backoff(receive_count: u32) -> Duration {
+    let exponent = receive_count.saturating_sub(1).min(10);
+    Duration::from_secs((BASE_BACKOFF_SECONDS << exponent).min(MAX_DELAY_SECONDS))
+}
@@ -0,0 +1,247 @@
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_runtime::{run, service_fn, tracing, Error, LambdaEvent};
+use sam_env_macros::sam_env;
+use serde::Serialize;
+use shared::subscription::{Subscription, SubscriptionStatus};
+use shared::{unix_now, EmailJob, SubscribeEventPayload};
+
+#[sam_env(template = "../../template.yaml")]
+struct SamEnv;
+
+struct Config {
+    env: SamEnv,
+    dynamodb_client: aws_sdk_dynamodb::Client,
+    sqs_client: aws_sdk_sqs::Client,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ReminderReport {
+    reminded: usize,
+    expiring: usize,
+}
+
+/// Applies an update to a pending subscription, returning false when it
+/// changed since it was read (confirmed, deleted, or handled by another
+/// run).
+async fn update_pending(
+    subscription: &Subscription,
+    update_expression: &str,
+    values: &[(&str, AttributeValue)],
+    config: &Config,
+) -> Result<bool, Error> {
+    let mut request = config
+        .dynamodb_client
+        .update_item()
+        .table_name(&config.env.subscriptions_table)
+        .key(
+            "campaign_id",
+            AttributeValue::S(subscription.campaign_id.clone()),
+        )
+        .key(
+            "subscription_id",
+            AttributeValue::S(subscription.subscription_id.clone()),
+        )
+        .update_expression(update_expression)
+        .condition_expression(
+            "attribute_exists(subscription_id) AND attribute_not_exists(confirmed_at) \
+             AND attribute_not_exists(expires_at) \
+             AND (attribute_not_exists(reminders_sent) OR reminders_sent = :reminders_sent)",
+        )
+        .expression_attribute_values(
+            ":reminders_sent",
+            AttributeValue::N(subscription.reminders_sent.unwrap_or_default().to_string()),
+        );
+    for (name, value) in values {
+        request = request.expression_attribute_values(*name, value.clone());
+    }
+
+    match request.send().await {
+        Ok(_) => Ok(true),
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) =>
+        {
+            Ok(false)
+        }
+        Err(err) => Err(Box::new(err).into()),
+    }
+}
+
+/// The subscriptions waiting for a reminder are read in pages of this size,
+/// handled concurrently.
+const PAGE_SIZE: i32 = 100;
+
+enum Reminder {
+    Reminded,
+    Expiring,
+    Skipped,
+}
+
+async fn campaign_ids(config: &Config) -> Result<Vec<String>, Error> {
+    let items = config
+        .dynamodb_client
+        .scan()
+        .table_name(&config.env.campaigns_table)
+        .projection_expression("campaign_id")
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(Box::new)?;
+    Ok(items
+        .iter()
+        .filter_map(|item| item.get("campaign_id").and_then(|id| id.as_s().ok()))
+        .cloned()
+        .collect())
+}
+
+/// Reminds a subscriber whose last email is older than `REMINDER_AFTER`,
+/// or lets their subscription expire when they had all their reminders.
+async fn remind(subscription: Subscription, now: u64, config: &Config) -> Result<Reminder, Error> {
+    // confirmed (or unsubscribed) since the email was sent: it's not waiting
+    // for a reminder anymore
+    if subscription.status() != SubscriptionStatus::Pending || subscription.expires_at.is_some() {
+        config
+            .dynamodb_client
+            .update_item()
+            .table_name(&config.env.subscriptions_table)
+            .key(
+                "campaign_id",
+                AttributeValue::S(subscription.campaign_id.clone()),
+            )
+            .key(
+                "subscription_id",
+                AttributeValue::S(subscription.subscription_id.clone()),
+            )
+            .update_expression("REMOVE pending_sent_at")
+            .send()
+            .await
+            .map_err(Box::new)?;
+        return Ok(Reminder::Skipped);
+    }
+
+    // nothing more can be sent to a suppressed address
+    let reminders_sent = subscription.reminders_sent.unwrap_or_default();
+    if reminders_sent >= config.env.max_reminders || subscription.suppressed_at.is_some() {
+        let expires_at = now + config.env.pending_expiry.as_secs();
+        let expiring = update_pending(
+            &subscription,
+            "SET expires_at = :expires_at REMOVE pending_sent_at",
+            &[(":expires_at", AttributeValue::N(expires_at.to_string()))],
+            config,
+        )
+        .await?;
+        return Ok(match expiring {
+            true => Reminder::Expiring,
+            false => Reminder::Skipped,
+        });
+    }
+
+    // counted first, so that a failure can't lead to more reminders
+    if !update_pending(
+        &subscription,
+        "SET reminders_sent = :next, reminded_at = :now, pending_sent_at = :now",
+        &[
+            (":next", AttributeValue::N((reminders_sent + 1).to_string())),
+            (":now", AttributeValue::N(now.to_string())),
+        ],
+        config,
+    )
+    .await?
+    {
+        return Ok(Reminder::Skipped);
+    }
+    let job = EmailJob::Reminder(SubscribeEventPayload {
+        subscription_id: subscription.subscription_id,
+        campaign_id: subscription.campaign_id,
+        email: subscription.email,
+        force: false,
+        locale: subscription.locale,
+    });
+    config
+        .sqs_client
+        .send_message()
+        .queue_url(&config.env.email_queue)
+        .message_body(serde_json::to_string(&job)?)
+        .send()
+        .await
+        .map_err(Box::new)?;
+    Ok(Reminder::Reminded)
+}
+
+/// Sends a reminder with a fresh token to the subscribers that didn't
+/// confirm within `REMINDER_AFTER` of the last email, up to
+/// `MAX_REMINDERS` times, then lets DynamoDB delete their subscription
+/// after `PENDING_EXPIRY`.
+///
+/// Only the pending subscriptions have a `pending_sent_at` (the time of
+/// their last email, or of the subscription until the first one is sent),
+/// so the sparse `pending-index` on it has just the ones to remind, those
+/// whose first email never went out included.
+async fn function_handler(
+    _event: LambdaEvent<serde_json::Value>,
+    config: &Config,
+) -> Result<ReminderReport, Error> {
+    let now = unix_now();
+    let due = now.saturating_sub(config.env.reminder_after.as_secs());
+    let mut report = ReminderReport::default();
+    for campaign_id in campaign_ids(config).await? {
+        let mut pages = config
+            .dynamodb_client
+            .query()
+            .table_name(&config.env.subscriptions_table)
+            .index_name("pending-index")
+            .key_condition_expression("campaign_id = :campaign_id AND pending_sent_at <= :due")
+            .expression_attribute_values(":campaign_id", AttributeValue::S(campaign_id))
+            .expression_attribute_values(":due", AttributeValue::N(due.to_string()))
+            .limit(PAGE_SIZE)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let subscriptions: Vec<Subscription> =
+                serde_dynamo::from_items(page.map_err(Box::new)?.items.unwrap_or_default())?;
+            let reminders = futures::future::join_all(
+                subscriptions
+                    .into_iter()
+                    .map(|subscription| remind(subscription, now, config)),
+            )
+            .await;
+            for reminder in reminders {
+                match reminder? {
+                    Reminder::Reminded => report.reminded += 1,
+                    Reminder::Expiring => report.expiring += 1,
+                    Reminder::Skipped => {}
+                }
+            }
+        }
+    }
+
+    tracing::info!(
+        reminded = report.reminded,
+        expiring = report.expiring,
+        "Reminders sent"
+    );
+    Ok(report)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let env = SamEnv::from_env()?;
+
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = aws_sdk_dynamodb::Client::new(&config);
+    let sqs_client = aws_sdk_sqs::Client::new(&config);
+
+    let config = Config {
+        env,
+        dynamodb_client,
+        sqs_client,
+    };
+
+    tracing::init_default_subscriber();
+
+    run(service_fn(|event| function_handler(event, &config))).await
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use aws_lambda_events::event::sns::SnsEvent;
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_runtime::{run, service_fn, tracing, Error, LambdaEvent};
+use sam_env_macros::sam_env;
+use serde::Deserialize;
+use shared::email::normalize_email;
+use shared::suppression::{Suppression, SuppressionReason};
+use shared::unix_now;
+
+#[sam_env(template = "../../template.yaml")]
+struct SamEnv;
+
+struct Config {
+    env: SamEnv,
+    dynamodb_client: aws_sdk_dynamodb::Client,
+}
+
+/// A notification published by SES, either by the configuration set
+/// (`eventType`) or by the identity (`notificationType`).
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SesNotification {
+    #[serde(alias = "notificationType")]
+    event_type: String,
+    mail: Mail,
+    bounce: Option<Bounce>,
+    complaint: Option<Complaint>,
+    delivery: Option<Delivery>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Mail {
+    message_id: String,
+    #[serde(default)]
+    tags: HashMap<String, Vec<String>>,
+}
+
+impl Mail {
+    fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .get(name)
+            .and_then(|values| values.first())
+            .map(|value| value.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Bounce {
+    bounce_type: String,
+    bounce_sub_type: String,
+    bounced_recipients: Vec<Recipient>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Complaint {
+    complained_recipients: Vec<Recipient>,
+    complaint_feedback_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Recipient {
+    email_address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Delivery {}
+
+/// Adds the address to the suppression list, keeping the first reason it
+/// was suppressed for, and marks all its subscriptions as suppressed.
+async fn suppress(
+    email: &str,
+    reason: SuppressionReason,
+    detail: Option<String>,
+    ses_message_id: &str,
+    config: &Config,
+) -> Result<(), Error> {
+    let email = normalize_email(email).unwrap_or_else(|| email.to_string());
+    let now = unix_now();
+    let suppression = Suppression {
+        email: email.clone(),
+        reason,
+        detail,
+        ses_message_id: Some(ses_message_id.to_string()),
+        created_at: now,
+    };
+    let result = config
+        .dynamodb_client
+        .put_item()
+        .table_name(&config.env.suppressions_table)
+        .set_item(Some(serde_dynamo::to_item(&suppression)?))
+        .condition_expression("attribute_not_exists(email)")
+        .send()
+        .await;
+    match result {
+        Ok(_) => tracing::info!("Suppressed address: {:?}", suppression),
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) => {}
+        Err(err) => return Err(Box::new(err).into()),
+    }
+
+    let keys = config
+        .dynamodb_client
+        .query()
+        .table_name(&config.env.subscriptions_table)
+        .index_name("email-index")
+        .key_condition_expression("email = :email")
+        .expression_attribute_values(":email", AttributeValue::S(email))
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .map_err(Box::new)?;
+    for key in keys {
+        let (Some(campaign_id), Some(subscription_id)) =
+            (key.get("campaign_id"), key.get("subscription_id"))
+        else {
+            continue;
+        };
+        config
+            .dynamodb_client
+            .update_item()
+            .table_name(&config.env.subscriptions_table)
+            .key("campaign_id", campaign_id.clone())
+            .key("subscription_id", subscription_id.clone())
+            .update_expression("SET suppressed_at = if_not_exists(suppressed_at, :now)")
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .send()
+            .await
+            .map_err(Box::new)?;
+    }
+
+    Ok(())
+}
+
+/// Records the delivery on the subscription the email was sent for, found
+/// through the tags of the message.
+async fn record_delivery(mail: &Mail, config: &Config) -> Result<(), Error> {
+    let (Some(campaign_id), Some(subscription_id)) =
+        (mail.tag("campaign_id"), mail.tag("subscription_id"))
+    else {
+        return Ok(());
+    };
+    let result = config
+        .dynamodb_client
+        .update_item()
+        .table_name(&config.env.subscriptions_table)
+        .key("campaign_id", AttributeValue::S(campaign_id.to_string()))
+        .key(
+            "subscription_id",
+            AttributeValue::S(subscription_id.to_string()),
+        )
+        .update_expression("SET delivered_at = :now")
+        .condition_expression("attribute_exists(subscription_id)")
+        .expression_attribute_values(":now", AttributeValue::N(unix_now().to_string()))
+        .send()
+        .await;
+    match result {
+        Ok(_) => Ok(()),
+        // deleted since it was sent
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) =>
+        {
+            Ok(())
+        }
+        Err(err) => Err(Box::new(err).into()),
+    }
+}
+
+async fn function_handler(event: LambdaEvent<SnsEvent>, config: &Config) -> Result<(), Error> {
+    for record in event.payload.records {
+        let notification: SesNotification = serde_json::from_str(&record.sns.message)?;
+        let mail = &notification.mail;
+
+        match (
+            notification.event_type.as_str(),
+            &notification.bounce,
+            &notification.complaint,
+            &notification.delivery,
+        ) {
+            // only the permanent bounces are suppressed, SES retries the
+            // transient ones already
+            ("Bounce", Some(bounce), _, _) if bounce.bounce_type == "Permanent" => {
+                for recipient in &bounce.bounced_recipients {
+                    suppress(
+                        &recipient.email_address,
+                        SuppressionReason::Bounce,
+                        Some(bounce.bounce_sub_type.clone()),
+                        &mail.message_id,
+                        config,
+                    )
+                    .await?;
+                }
+            }
+            ("Bounce", Some(bounce), _, _) => {
+                tracing::info!(
+                    ses_message_id = mail.message_id,
+                    "Ignoring {} bounce",
+                    bounce.bounce_type
+                );
+            }
+            ("Complaint", _, Some(complaint), _) => {
+                for recipient in &complaint.complained_recipients {
+                    suppress(
+                        &recipient.email_address,
+                        SuppressionReason::Complaint,
+                        complaint.complaint_feedback_type.clone(),
+                        &mail.message_id,
+                        config,
+                    )
+                    .await?;
+                }
+            }
+            ("Delivery", _, _, Some(_)) => record_delivery(mail, config).await?,
+            (event_type, _, _, _) => {
+                tracing::warn!(
+                    ses_message_id = mail.message_id,
+                    "Unexpected notification: {}",
+                    event_type
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let env = SamEnv::from_env()?;
+
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = aws_sdk_dynamodb::Client::new(&config);
+
+    let config = Config {
+        env,
+        dynamodb_client,
+    };
+
+    tracing::init_default_subscriber();
+
+    run(service_fn(|event| function_handler(event, &config))).await
+}
@@ -1,32 +1,28 @@
+use std::collections::HashMap;
+
 use aws_sdk_dynamodb::types::AttributeValue;
 use lambda_http::request::RequestContext;
 use lambda_http::{
     run, service_fn, tracing, Body, Error, Request, RequestExt, RequestPayloadExt, Response,
 };
+use sam_env_macros::sam_env;
 use serde::Deserialize;
-use shared::SubscribeEventPayload;
-use std::env;
-use validators::models::Host;
-use validators::prelude::*;
-include!(concat!(env!("OUT_DIR"), "/sam_env.rs"));
-
-#[derive(Validator)]
-#[validator(email(
-    comment(Disallow),
-    ip(Allow),
-    local(Allow),
-    at_least_two_labels(Allow),
-    non_ascii(Allow)
-))]
-pub struct Email {
-    pub local_part: String,
-    pub need_quoted: bool,
-    pub domain_part: Host,
-}
+use shared::fields::parse_fields;
+use shared::i18n::Text;
+use shared::render::{escape_html, render_text};
+use shared::{campaign::Campaign, email::normalize_email, unix_now, SubscribeEventPayload};
+
+#[sam_env(template = "../../template.yaml")]
+struct SamEnv;
 
 #[derive(Debug, Deserialize)]
 struct FormPayload {
     email: String,
+    /// The locale the form was rendered in
+    locale: Option<String>,
+    /// The extra fields of the campaign (and anything else, ignored)
+    #[serde(flatten)]
+    values: HashMap<String, String>,
 }
 
 #[derive(Debug)]
@@ -38,8 +34,8 @@ struct Config {
 
 async fn function_handler(event: Request, config: &Config) -> Result<Response<Body>, Error> {
     /*
-       1. validate email
-       2. validate campaign_id
+       1. validate campaign_id
+       2. validate email
        3. create subscription record
        4. put send_confirmation_email job in the queue
        (OPTIONAL) 5. send eventbridge event subscription_started
@@ -58,16 +54,7 @@ async fn function_handler(event: Request, config: &Config) -> Result<Response<Bo
         .expect("Invalid payload, could not deserialize.")
         .expect("Payload missing");
 
-    // 1. validate email
-    if Email::parse_str(&payload.email).is_err() {
-        return Ok(Response::builder()
-            .status(400)
-            .header("content-type", "text/html")
-            .body("Invalid email".into())
-            .map_err(Box::new)?);
-    }
-
-    // 2. validate campaign_id
+    // 1. validate campaign_id
     let campaign = config
         .dynamodb_client
         .get_item()
@@ -77,13 +64,60 @@ async fn function_handler(event: Request, config: &Config) -> Result<Response<Bo
         .await
         .expect("Failed to get campaign");
 
-    if campaign.item.is_none() {
+    // archived campaigns don't accept new subscriptions
+    let campaign: Option<Campaign> = campaign.item.map(serde_dynamo::from_item).transpose()?;
+    let Some(campaign) = campaign.filter(|c| c.is_active()) else {
         return Ok(Response::builder()
             .status(404)
             .header("content-type", "text/html")
-            .body("Campaign not found".into())
+            .body(Text::CampaignNotFound.default_text().into())
             .map_err(Box::new)?);
-    }
+    };
+
+    // the texts are in the locale of the form, or the best one for the visitor
+    let accept_language = event
+        .headers()
+        .get("accept-language")
+        .and_then(|value| value.to_str().ok());
+    let locale = campaign.negotiate_locale(payload.locale.as_deref(), accept_language);
+
+    // 2. validate email
+    let Some(email) = normalize_email(&payload.email) else {
+        return Ok(Response::builder()
+            .status(400)
+            .header("content-type", "text/html")
+            .header("content-language", &locale)
+            .body(escape_html(campaign.text(&locale, Text::InvalidEmail)).into())
+            .map_err(Box::new)?);
+    };
+    let fields = match parse_fields(&campaign.fields, &payload.values) {
+        Ok(fields) => fields,
+        Err(invalid) => {
+            let message = escape_html(&render_text(
+                campaign.text(&locale, Text::InvalidField),
+                &[("field", &invalid.join(", "))],
+            ));
+            return Ok(Response::builder()
+                .status(400)
+                .header("content-type", "text/html")
+                .header("content-language", &locale)
+                .body(message.into())
+                .map_err(Box::new)?);
+        }
+    };
+
+    // addresses that bounced or complained are saved, without sending them
+    // the confirmation
+    let suppressed = config
+        .dynamodb_client
+        .get_item()
+        .table_name(&config.env.suppressions_table)
+        .key("email", AttributeValue::S(email.clone()))
+        .send()
+        .await
+        .map_err(Box::new)?
+        .item
+        .is_some();
 
     // 3. save subscription record
     let ip: String = match request_context {
@@ -94,8 +128,9 @@ async fn function_handler(event: Request, config: &Config) -> Result<Response<Bo
     };
 
     let subscription_id = cuid::cuid2();
-    // subscription_id | campaign_id | IP | fingerprint | email | sent_at | opened_at | confirmed_at | unsubscribed_at
-    config
+    let now = unix_now().to_string();
+    // subscription_id | campaign_id | IP | fingerprint | email | created_at | sent_at | opened_at | confirmed_at | unsubscribed_at
+    let mut put_subscription = config
         .dynamodb_client
         .put_item()
         .table_name(&config.env.subscriptions_table)
@@ -104,39 +139,62 @@ async fn function_handler(event: Request, config: &Config) -> Result<Response<Bo
             AttributeValue::S(subscription_id.to_string()),
         )
         .item("campaign_id", AttributeValue::S(campaign_id.to_string()))
-        .item("email", AttributeValue::S(payload.email.to_string()))
+        .item("email", AttributeValue::S(email.clone()))
         .item("ip", AttributeValue::S(ip))
+        .item("locale", AttributeValue::S(locale.clone()))
+        .item("created_at", AttributeValue::N(now.clone()))
+        // reminded (or expired) from now if the confirmation is never sent
+        .item("pending_sent_at", AttributeValue::N(now.clone()));
+    if !fields.is_empty() {
+        put_subscription =
+            put_subscription.item("fields", serde_dynamo::to_attribute_value(&fields)?);
+    }
+    if suppressed {
+        put_subscription = put_subscription.item("suppressed_at", AttributeValue::N(now));
+    }
+    put_subscription
         .send()
         .await
         .expect("Failed to save subscription");
 
     // 4. put send_confirmation_email job in the queue
-    let sqs_message_body = SubscribeEventPayload {
-        subscription_id,
-        campaign_id: campaign_id.to_string(),
-        email: payload.email.clone(),
-    };
-    let result = config
-        .sqs_client
-        .send_message()
-        .queue_url(&config.env.email_queue)
-        .message_body(serde_json::to_string(&sqs_message_body).unwrap())
-        .send()
-        .await;
-
-    // TODO: if this fails, we need to delete the subscription record and return a 502 error
-    let sqs_message_id = result.unwrap().message_id.unwrap();
-    tracing::info!(id = sqs_message_id, "Inserted message in the queue");
-
-    let message = format!("Hello {campaign_id}, {}", payload.email);
+    if suppressed {
+        tracing::info!(
+            subscription_id,
+            "Not sending the confirmation to a suppressed address"
+        );
+    } else {
+        let sqs_message_body = SubscribeEventPayload {
+            subscription_id,
+            campaign_id: campaign_id.to_string(),
+            email: email.clone(),
+            force: false,
+            locale: Some(locale.clone()),
+        };
+        let result = config
+            .sqs_client
+            .send_message()
+            .queue_url(&config.env.email_queue)
+            .message_body(serde_json::to_string(&sqs_message_body).unwrap())
+            .send()
+            .await;
+
+        // TODO: if this fails, we need to delete the subscription record and return a 502 error
+        let sqs_message_id = result.unwrap().message_id.unwrap();
+        tracing::info!(id = sqs_message_id, "Inserted message in the queue");
+    }
 
-    // TODO: start from here --- put message in the Queue
+    let message = escape_html(&render_text(
+        campaign.text(&locale, Text::Subscribed),
+        &[("email", &email)],
+    ));
 
     // Return something that implements IntoResponse.
     // It will be serialized to the right response event automatically by the runtime
     let resp = Response::builder()
         .status(200)
         .header("content-type", "text/html")
+        .header("content-language", &locale)
         .body(message.into())
         .map_err(Box::new)?;
     Ok(resp)
@@ -144,7 +202,7 @@ async fn function_handler(event: Request, config: &Config) -> Result<Response<Bo
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let env = SamEnv::init_from_env().unwrap();
+    let env = SamEnv::from_env()?;
 
     let config = aws_config::load_from_env().await;
     let dynamodb_client = aws_sdk_dynamodb::Client::new(&config);
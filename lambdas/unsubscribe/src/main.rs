@@ -1,31 +1,161 @@
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
 use lambda_http::{run, service_fn, tracing, Body, Error, Request, RequestExt, Response};
-include!(concat!(env!("OUT_DIR"), "/sam_env.rs"));
-
-/// This is the main body for the function.
-/// Write your code inside it.
-/// There are some code example in the following URLs:
-/// - https://github.com/awslabs/aws-lambda-rust-runtime/tree/main/examples
-async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
-    // Extract some useful information from the request
-    let who = event
+use sam_env_macros::sam_env;
+use shared::campaign::Campaign;
+use shared::i18n::Text;
+use shared::render::escape_html;
+use shared::{keyring::Keyring, unix_now, UnsubscribeTokenClaims};
+
+#[sam_env(template = "../../template.yaml")]
+struct SamEnv;
+
+struct Config {
+    env: SamEnv,
+    dynamodb_client: aws_sdk_dynamodb::Client,
+    keyring: Keyring,
+}
+
+fn html_response(
+    status: u16,
+    locale: Option<&str>,
+    message: &str,
+) -> Result<Response<Body>, Error> {
+    let mut response = Response::builder()
+        .status(status)
+        .header("content-type", "text/html");
+    if let Some(locale) = locale {
+        response = response.header("content-language", locale);
+    }
+    Ok(response
+        .body(
+            format!(
+                "<!DOCTYPE html><html><body><p>{}</p></body></html>",
+                escape_html(message)
+            )
+            .into(),
+        )
+        .map_err(Box::new)?)
+}
+
+/// Responds with a text of the campaign, in the locale the subscriber chose
+/// or else the best one for the visitor. Without the campaign (e.g. deleted
+/// since), the text is in English.
+fn text_response(
+    status: u16,
+    campaign: Option<&Campaign>,
+    requested: Option<&str>,
+    event: &Request,
+    text: Text,
+) -> Result<Response<Body>, Error> {
+    let Some(campaign) = campaign else {
+        return html_response(status, None, text.default_text());
+    };
+    let accept_language = event
+        .headers()
+        .get("accept-language")
+        .and_then(|value| value.to_str().ok());
+    let locale = campaign.negotiate_locale(requested, accept_language);
+    html_response(status, Some(&locale), campaign.text(&locale, text))
+}
+
+async fn function_handler(event: Request, config: &Config) -> Result<Response<Body>, Error> {
+    let subscription_id = event
+        .path_parameters_ref()
+        .and_then(|params| params.first("subscription_id"))
+        .unwrap_or_default();
+    // the token has to be for the subscription in the path
+    let token = event
         .query_string_parameters_ref()
-        .and_then(|params| params.first("name"))
-        .unwrap_or("world");
-    let message = format!("Hello {who}, this is an AWS Lambda HTTP request");
-
-    // Return something that implements IntoResponse.
-    // It will be serialized to the right response event automatically by the runtime
-    let resp = Response::builder()
-        .status(200)
-        .header("content-type", "text/html")
-        .body(message.into())
-        .map_err(Box::new)?;
-    Ok(resp)
+        .and_then(|params| params.first("token"))
+        .and_then(|token| config.keyring.decode::<UnsubscribeTokenClaims>(token).ok())
+        .filter(|claims| claims.subscription_id == subscription_id);
+    let Some(claims) = token else {
+        return html_response(400, None, Text::InvalidUnsubscribeLink.default_text());
+    };
+
+    let campaign: Option<Campaign> = config
+        .dynamodb_client
+        .get_item()
+        .table_name(&config.env.campaigns_table)
+        .key("campaign_id", AttributeValue::S(claims.campaign_id.clone()))
+        .send()
+        .await
+        .map_err(Box::new)?
+        .item
+        .map(serde_dynamo::from_item)
+        .transpose()?;
+
+    // keeps the first unsubscription, so clicking the link twice is fine
+    let result = config
+        .dynamodb_client
+        .update_item()
+        .table_name(&config.env.subscriptions_table)
+        .key("campaign_id", AttributeValue::S(claims.campaign_id.clone()))
+        .key(
+            "subscription_id",
+            AttributeValue::S(claims.subscription_id.clone()),
+        )
+        .update_expression("SET unsubscribed_at = if_not_exists(unsubscribed_at, :now)")
+        .condition_expression("attribute_exists(subscription_id)")
+        .expression_attribute_values(":now", AttributeValue::N(unix_now().to_string()))
+        .return_values(ReturnValue::AllNew)
+        .send()
+        .await;
+
+    match result {
+        Ok(output) => {
+            tracing::info!(
+                campaign_id = claims.campaign_id,
+                subscription_id = claims.subscription_id,
+                "Unsubscribed"
+            );
+            let locale = output
+                .attributes
+                .as_ref()
+                .and_then(|subscription| subscription.get("locale"))
+                .and_then(|locale| locale.as_s().ok());
+            text_response(
+                200,
+                campaign.as_ref(),
+                locale.map(String::as_str),
+                &event,
+                Text::Unsubscribed,
+            )
+        }
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_conditional_check_failed_exception())
+                .unwrap_or(false) =>
+        {
+            text_response(
+                400,
+                campaign.as_ref(),
+                None,
+                &event,
+                Text::InvalidSubscription,
+            )
+        }
+        Err(err) => Err(Box::new(err).into()),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
+    let env = SamEnv::from_env()?;
+
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = aws_sdk_dynamodb::Client::new(&config);
+    let env = env.resolve_secrets(&config).await?;
+    let keyring = Keyring::from_json(&env.token_keyring)?;
+
+    let config = Config {
+        env,
+        dynamodb_client,
+        keyring,
+    };
+
     tracing::init_default_subscriber();
 
-    run(service_fn(function_handler)).await
+    run(service_fn(|event| function_handler(event, &config))).await
 }
@@ -0,0 +1,337 @@
+use crate::intrinsics::Expr;
+use crate::variables::{VarType, Variable};
+
+/// How the generated struct is declared.
+pub struct StructDecl<'a> {
+    /// Attributes (doc comments included) to put on the struct
+    pub attributes: &'a str,
+    pub visibility: &'a str,
+    pub name: &'a str,
+}
+
+/// Generates the struct holding the environment of the function, along with
+/// the helpers it needs.
+///
+/// Everything lives in a module named after the struct and the struct is then
+/// re-exported, so that more structs can be generated in the same scope.
+pub fn generate(decl: &StructDecl, variables: &[Variable]) -> String {
+    let props: Vec<_> = variables
+        .iter()
+        .map(|v| {
+            let attribute = match &v.default {
+                Some(default) if !v.optional => {
+                    format!("from = {:?}, default = {:?}", v.name, default)
+                }
+                _ => format!("from = {:?}", v.name),
+            };
+            format!(
+                r##"
+#[envconfig({})]
+pub {}: {},"##,
+                attribute,
+                v.field_name(),
+                v.field_type()
+            )
+        })
+        .collect();
+
+    let mut items = format!(
+        r#"use envconfig::Envconfig;
+
+{}
+#[derive(Debug, Envconfig)]
+pub struct {} {{
+    {}
+}}"#,
+        decl.attributes,
+        decl.name,
+        props.join("\n")
+    );
+
+    items.push_str(&constructors_impl(decl.name, variables));
+
+    if variables.iter().any(|v| v.var_type.needs_helpers()) {
+        items.push_str(&format!("\n\nmod types {{\n{}}}", TYPES));
+    }
+
+    if variables.iter().any(|v| v.secret) {
+        items.push_str(&secrets_impl(decl.name, variables));
+    }
+
+    let module = module_name(decl.name);
+    format!(
+        r#"mod {module} {{
+{items}
+}}
+
+{} use {module}::{};"#,
+        decl.visibility, decl.name
+    )
+}
+
+/// `__sam_env_` followed by the snake case version of the struct name.
+fn module_name(struct_name: &str) -> String {
+    let mut module = String::from("__sam_env_");
+    for (i, c) in struct_name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            module.push('_');
+        }
+        module.extend(c.to_lowercase());
+    }
+    module
+}
+
+/// Generates the `from_env` and `local` constructors, wrapping `Envconfig`
+/// with errors describing what the offending variable should look like.
+fn constructors_impl(struct_name: &str, variables: &[Variable]) -> String {
+    let arms: Vec<_> = variables
+        .iter()
+        .filter(|v| v.var_type != VarType::String)
+        .map(|v| {
+            format!(
+                r#"
+                    {:?} => {:?},"#,
+                v.name,
+                v.var_type.expected()
+            )
+        })
+        .collect();
+    let expected = if arms.is_empty() {
+        r#""a valid value""#.to_string()
+    } else {
+        format!(
+            r#"match name {{{}
+                    _ => "a valid value",
+                }}"#,
+            arms.join("")
+        )
+    };
+
+    let needs_lookup = variables
+        .iter()
+        .any(|v| !matches!(v.value, Expr::Literal(_)));
+    let lookup = if needs_lookup {
+        r#"
+        let lookup = |key: &str| -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+            values
+                .get(key)
+                .map(|value| value.to_string())
+                .ok_or_else(|| format!("Missing local value for {key}").into())
+        };"#
+    } else {
+        r#"
+        let _ = values;"#
+    };
+    let inserts: Vec<_> = variables
+        .iter()
+        .map(|v| {
+            format!(
+                r#"
+        env.insert({:?}.to_string(), {});"#,
+                v.name,
+                v.value.resolver(&v.name)
+            )
+        })
+        .collect();
+
+    // envconfig doesn't support defaults for an `Option`, they are applied
+    // to the variables before handing them over
+    let optional_defaults: Vec<_> = variables
+        .iter()
+        .filter_map(|v| {
+            v.optional_default()
+                .map(|default| format!("({:?}, {:?})", v.name, default))
+        })
+        .collect();
+    let (from_env, apply_defaults, defaults_impl) = if optional_defaults.is_empty() {
+        ("Self::init_from_env()", "", String::new())
+    } else {
+        (
+            r#"{
+            let mut env = std::env::vars_os()
+                .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+                .collect();
+            Self::apply_defaults(&mut env);
+            Self::init_from_hashmap(&env)
+        }"#,
+            r#"
+        Self::apply_defaults(&mut env);"#,
+            format!(
+                r#"
+
+    /// Optional variables default to their default when they are not set,
+    /// and are `None` when set to an empty value.
+    fn apply_defaults(env: &mut std::collections::HashMap<String, String>) {{
+        for (name, default) in [{}] {{
+            match env.get(name).map(String::as_str) {{
+                None => {{
+                    env.insert(name.to_string(), default.to_string());
+                }}
+                Some("") => {{
+                    env.remove(name);
+                }}
+                Some(_) => {{}}
+            }}
+        }}
+    }}"#,
+                optional_defaults.join(", ")
+            ),
+        )
+    };
+
+    format!(
+        r#"
+
+impl {struct_name} {{
+    /// Loads the configuration from the environment, failing with an error
+    /// that describes the expected format of the offending variable.
+    pub fn from_env() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {{
+        {from_env}.map_err(Self::describe_error)
+    }}
+
+    /// Builds the configuration from the values of the variables in the
+    /// template, resolving `!Ref`, `!GetAtt` and `!Sub` against `values`:
+    /// parameters, pseudo parameters (`AWS::Region`), logical IDs and
+    /// `Resource.Attribute` keys. Variables with any other intrinsic function
+    /// are looked up by their own name.
+    pub fn local(
+        values: &std::collections::HashMap<&str, &str>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {{{lookup}
+        let mut env = std::collections::HashMap::new();{inserts}{apply_defaults}
+        Self::init_from_hashmap(&env).map_err(Self::describe_error)
+    }}{defaults_impl}
+
+    fn describe_error(err: envconfig::Error) -> Box<dyn std::error::Error + Send + Sync> {{
+        match err {{
+            envconfig::Error::ParseError {{ name }} => {{
+                let expected = {expected};
+                format!("Failed to parse environment variable {{name}}: expected {{expected}}").into()
+            }}
+            err => err.into(),
+        }}
+    }}
+}}"#,
+        inserts = inserts.join("")
+    )
+}
+
+/// Generates the content of a `.env` file for the function: literal values
+/// are filled in, the others are left empty with the expression to resolve.
+pub fn env_example(header: &str, variables: &[Variable]) -> String {
+    let mut output = format!("# {}\n", header);
+    for v in variables {
+        output.push('\n');
+        match &v.value {
+            Expr::Literal(value) => output.push_str(&format!("{}={}\n", v.name, value)),
+            expr => output.push_str(&format!("# {}\n{}=\n", expr, v.name)),
+        }
+    }
+    output
+}
+
+/// The helper types, kept in their own file so that they can be tested.
+const TYPES: &str = include_str!("codegen/types.rs");
+
+/// Generates a `resolve_secrets` method that replaces the secret references
+/// found in the environment (`ssm:<name>`, `secretsmanager:<id>` or the ARN
+/// of either) with the actual secret values.
+///
+/// The generated code needs `aws-config`, `aws-sdk-ssm` and
+/// `aws-sdk-secretsmanager` in the dependencies of the crate including it.
+fn secrets_impl(struct_name: &str, variables: &[Variable]) -> String {
+    let resolutions: Vec<_> = variables
+        .iter()
+        .filter(|v| v.secret)
+        .map(|v| {
+            if v.optional {
+                format!(
+                    r#"
+        if let Some(reference) = self.{field}.take() {{
+            self.{field} = Some(secrets::resolve(sdk_config, "{name}", &reference).await?);
+        }}"#,
+                    field = v.field_name(),
+                    name = v.name
+                )
+            } else {
+                format!(
+                    r#"
+        self.{field} = secrets::resolve(sdk_config, "{name}", &self.{field}).await?;"#,
+                    field = v.field_name(),
+                    name = v.name
+                )
+            }
+        })
+        .collect();
+
+    format!(
+        r#"
+
+impl {struct_name} {{
+    /// Replaces the references to the secrets with their values, fetched from
+    /// SSM Parameter Store or Secrets Manager. Values are cached, so this is
+    /// meant to be called once at cold start.
+    pub async fn resolve_secrets(
+        mut self,
+        sdk_config: &aws_config::SdkConfig,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {{
+        {resolutions}
+        Ok(self)
+    }}
+}}
+
+mod secrets {{
+    use std::collections::HashMap;
+    use std::sync::{{Mutex, OnceLock}};
+
+    static CACHE: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+
+    pub async fn resolve(
+        sdk_config: &aws_config::SdkConfig,
+        name: &str,
+        reference: &str,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {{
+        let cache = CACHE.get_or_init(Default::default);
+        if let Some(value) = cache.lock().unwrap().get(reference) {{
+            return Ok(value.clone());
+        }}
+
+        let value = if let Some(id) = reference
+            .strip_prefix("secretsmanager:")
+            .or_else(|| reference.starts_with("arn:aws:secretsmanager:").then_some(reference))
+        {{
+            aws_sdk_secretsmanager::Client::new(sdk_config)
+                .get_secret_value()
+                .secret_id(id)
+                .send()
+                .await?
+                .secret_string
+        }} else if let Some(parameter) = reference
+            .strip_prefix("ssm:")
+            .or_else(|| reference.starts_with("arn:aws:ssm:").then_some(reference))
+        {{
+            aws_sdk_ssm::Client::new(sdk_config)
+                .get_parameter()
+                .name(parameter)
+                .with_decryption(true)
+                .send()
+                .await?
+                .parameter
+                .and_then(|p| p.value)
+        }} else {{
+            return Err(format!(
+                "{{name}} should reference a secret as ssm:<name> or secretsmanager:<id>, found: {{reference}}"
+            )
+            .into());
+        }};
+
+        let value = value.ok_or_else(|| format!("Secret {{name}} ({{reference}}) has no value"))?;
+        cache
+            .lock()
+            .unwrap()
+            .insert(reference.to_string(), value.clone());
+        Ok(value)
+    }}
+}}"#,
+        resolutions = resolutions.join("")
+    )
+}
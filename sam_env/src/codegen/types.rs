@@ -0,0 +1,67 @@
+//! Helper types of the generated structs, included as the `types` module of
+//! the generated code.
+
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// Seconds (`86400`) or a number with a unit (`500ms`, `60s`, `15m`, `24h`, `7d`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Duration(pub std::time::Duration);
+
+impl FromStr for Duration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (amount, unit) = s.split_at(split);
+        let amount: u64 = amount
+            .parse()
+            .map_err(|_| format!("Invalid duration: {s}"))?;
+        let multiplier = match unit.trim() {
+            "ms" => return Ok(Self(std::time::Duration::from_millis(amount))),
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 60 * 60 * 24,
+            _ => return Err(format!("Invalid duration unit: {s}")),
+        };
+        let seconds = amount
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("Duration too large: {s}"))?;
+        Ok(Self(std::time::Duration::from_secs(seconds)))
+    }
+}
+
+impl Deref for Duration {
+    type Target = std::time::Duration;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Comma-separated values, empty items are skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct List<T>(pub Vec<T>);
+
+impl<T: FromStr> FromStr for List<T> {
+    type Err = T::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(T::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+    }
+}
+
+impl<T> Deref for List<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
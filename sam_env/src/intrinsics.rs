@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Result};
+use serde_yaml::Value;
+use std::fmt;
+
+/// The value of an environment variable in the template, either a literal or
+/// one of the intrinsic functions that can be resolved locally.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(String),
+    /// `!Ref Name`, a parameter, a pseudo parameter or a resource logical ID
+    Ref(String),
+    /// `!GetAtt Resource.Attribute`
+    GetAtt(String),
+    /// `!Sub "text ${Name} text"`
+    Sub(Vec<SubPart>),
+    /// Anything else (`!Join`, `!If`, ...), resolved by variable name
+    Unsupported(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubPart {
+    Text(String),
+    Var(Expr),
+}
+
+impl Expr {
+    pub fn parse(value: &Value) -> Result<Self> {
+        match value {
+            Value::String(s) => Ok(Expr::Literal(s.clone())),
+            Value::Number(n) => Ok(Expr::Literal(n.to_string())),
+            Value::Bool(b) => Ok(Expr::Literal(b.to_string())),
+            Value::Tagged(tagged) => Self::parse_function(&tagged.tag.to_string(), &tagged.value),
+            // long form: `{ "Ref": ... }`, `{ "Fn::GetAtt": ... }`, `{ "Fn::Sub": ... }`
+            Value::Mapping(mapping) if mapping.len() == 1 => {
+                let (key, argument) = mapping.iter().next().unwrap();
+                match key.as_str() {
+                    Some("Ref") => Self::parse_function("!Ref", argument),
+                    Some(function) if function.starts_with("Fn::") => {
+                        Self::parse_function(&format!("!{}", &function[4..]), argument)
+                    }
+                    _ => Ok(Expr::Unsupported(format!("{:?}", value))),
+                }
+            }
+            _ => Ok(Expr::Unsupported(format!("{:?}", value))),
+        }
+    }
+
+    /// Parses an intrinsic function given its short-form tag and argument.
+    fn parse_function(tag: &str, argument: &Value) -> Result<Self> {
+        match (tag, argument) {
+            ("!Ref", Value::String(name)) => Ok(Expr::Ref(name.clone())),
+            ("!GetAtt", Value::String(attribute)) => Ok(Expr::GetAtt(attribute.clone())),
+            ("!GetAtt", Value::Sequence(parts)) => Ok(Expr::GetAtt(
+                parts
+                    .iter()
+                    .map(|p| p.as_str().ok_or_else(|| anyhow!("Invalid {}", tag)))
+                    .collect::<Result<Vec<_>>>()?
+                    .join("."),
+            )),
+            ("!Sub", Value::String(template)) => Self::parse_sub(template, None),
+            ("!Sub", Value::Sequence(args)) => match args.as_slice() {
+                [Value::String(template), Value::Mapping(variables)] => {
+                    Self::parse_sub(template, Some(variables))
+                }
+                _ => Err(anyhow!("Invalid {}", tag)),
+            },
+            (tag, _) => Ok(Expr::Unsupported(tag.to_string())),
+        }
+    }
+
+    fn parse_sub(template: &str, variables: Option<&serde_yaml::Mapping>) -> Result<Self> {
+        let mut parts = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find("${") {
+            let (text, tail) = rest.split_at(start);
+            push_text(&mut parts, text);
+            let end = tail
+                .find('}')
+                .ok_or_else(|| anyhow!("Unterminated variable in !Sub {}", template))?;
+            let name = &tail[2..end];
+            match name.strip_prefix('!') {
+                // `${!Literal}` is written as `${Literal}`
+                Some(literal) => push_text(&mut parts, &format!("${{{}}}", literal)),
+                None => {
+                    let expr = match variables.and_then(|v| v.get(name)) {
+                        Some(value) => Self::parse(value)?,
+                        None if name.contains('.') => Expr::GetAtt(name.to_string()),
+                        None => Expr::Ref(name.to_string()),
+                    };
+                    parts.push(SubPart::Var(expr));
+                }
+            }
+            rest = &tail[end + 1..];
+        }
+        push_text(&mut parts, rest);
+
+        Ok(Expr::Sub(parts))
+    }
+
+    /// The text the value starts with, known before deploying.
+    pub fn literal_prefix(&self) -> &str {
+        match self {
+            Expr::Literal(s) => s,
+            Expr::Sub(parts) => match parts.first() {
+                Some(SubPart::Text(text)) => text,
+                _ => "",
+            },
+            _ => "",
+        }
+    }
+
+    /// Generates the Rust expression resolving the value at runtime, given a
+    /// `lookup` closure returning `Result<String, _>` for a key and the name
+    /// of the variable (used for unsupported expressions).
+    pub fn resolver(&self, name: &str) -> String {
+        match self {
+            Expr::Literal(s) => format!("{:?}.to_string()", s),
+            Expr::Ref(key) | Expr::GetAtt(key) => format!("lookup({:?})?", key),
+            Expr::Unsupported(_) => format!("lookup({:?})?", name),
+            Expr::Sub(parts) => {
+                let parts: Vec<_> = parts
+                    .iter()
+                    .map(|p| match p {
+                        SubPart::Text(text) => format!("{:?}.to_string()", text),
+                        SubPart::Var(expr) => expr.resolver(name),
+                    })
+                    .collect();
+                format!("[{}].concat()", parts.join(", "))
+            }
+        }
+    }
+}
+
+fn push_text(parts: &mut Vec<SubPart>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    match parts.last_mut() {
+        Some(SubPart::Text(previous)) => previous.push_str(text),
+        _ => parts.push(SubPart::Text(text.to_string())),
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal(s) => write!(f, "{}", s),
+            Expr::Ref(name) => write!(f, "!Ref {}", name),
+            Expr::GetAtt(attribute) => write!(f, "!GetAtt {}", attribute),
+            Expr::Sub(parts) => {
+                write!(f, "!Sub ")?;
+                for part in parts {
+                    match part {
+                        SubPart::Text(text) => write!(f, "{}", text)?,
+                        SubPart::Var(Expr::Ref(name)) | SubPart::Var(Expr::GetAtt(name)) => {
+                            write!(f, "${{{}}}", name)?
+                        }
+                        SubPart::Var(expr) => write!(f, "${{{}}}", expr)?,
+                    }
+                }
+                Ok(())
+            }
+            Expr::Unsupported(description) => write!(f, "{} (not resolved)", description),
+        }
+    }
+}
@@ -1,106 +1,235 @@
+mod codegen;
+mod intrinsics;
+mod policies;
+mod template;
+mod variables;
+
+pub use codegen::StructDecl;
+pub use policies::{Policy, PolicyReport, Requirement};
+
 use anyhow::Result;
-use serde_yaml::Value;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use template::{Function, Template};
+use variables::Variable;
 
+/// The environment variables of a SAM function
+/// (`AWS::Serverless::Function`, including the variables declared in
+/// `Globals.Function`, or `AWS::Lambda::Function`).
+///
+/// Variables can be described in the `Metadata.SamEnv.Variables` block of the
+/// function:
+///
+/// ```yaml
+/// Metadata:
+///   SamEnv:
+///     Package: subscribe # the cargo package the function is built from
+///     Variables:
+///       TOKEN_TTL:
+///         Type: Duration # String (default), u64, i64, u32, bool, Duration, Url, List, List<T>
+///         Default: 24h
+///       ALLOWED_ORIGINS:
+///         Type: List
+///         Optional: true # generates an Option<T>
+///       REMINDER_AFTER:
+///         Type: Duration
+///         Optional: true # with a Default, None when set to an empty value
+///         Default: 1d
+///       TOKEN_KEYRING:
+///         Secret: true # resolved from SSM or Secrets Manager (implied by a _SECRET suffix)
+/// ```
 #[derive(Debug)]
-pub struct SamEnvConfig {
-    pub template_path: PathBuf,
-    pub package_name: String,
-    pub output_path: String,
-    pub output_filename: String,
-    pub struct_name: String,
+pub struct FunctionEnv {
+    template_path: PathBuf,
+    logical_id: String,
+    code_uri: Option<String>,
+    policies: Option<Vec<Policy>>,
+    variables: Vec<Variable>,
 }
 
-pub fn write_sam_env(config: SamEnvConfig) -> Result<()> {
-    let template = std::fs::read(&config.template_path).map_err(|err| {
-        anyhow::anyhow!(
-            "Failed to read template file {:?}: {}",
-            &config.template_path,
-            err
-        )
-    })?;
-    let template: Value = serde_yaml::from_slice(&template)
-        .map_err(|err| anyhow::anyhow!("Failed to parse yaml template file: {}", err))?;
-
-    let resources = template.get("Resources");
-    let resources = match resources {
-        None => {
-            return Err(anyhow::anyhow!("Malformed yaml"));
-        }
-        Some(r) => r
-            .as_mapping()
-            .expect("Resources should be a mapping")
-            .into_iter()
-            .filter(|(_, value)| {
-                value
-                    .get("Properties")
-                    .and_then(|p| p.get("CodeUri"))
-                    .and_then(|c| c.as_str())
-                    .map(|c| c.ends_with(&format!("/{}", config.package_name)))
-                    .unwrap_or(false)
-            })
-            .map(|(_, v)| v)
-            .collect::<Vec<_>>(),
-    };
-
-    if resources.len() != 1 {
-        return Err(anyhow::anyhow!(
-            "Expect to find exactly one resource with CodeUri ends with {}. Found: {}",
-            config.package_name,
-            resources.len()
-        ));
+/// Identifies the functions in the template.
+#[derive(Debug, Clone)]
+pub enum FunctionSelector {
+    /// The logical ID of the resource
+    LogicalId(String),
+    /// The functions built from a cargo package, matched (in order of
+    /// precedence) by `Metadata.SamEnv.Package`, by the cargo-lambda
+    /// `Metadata.BuildProperties.Binary` (the binary name, defaulting to the
+    /// package name) or by the end of `CodeUri`
+    Package {
+        package: String,
+        binary: Option<String>,
+    },
+    /// Every function in the template
+    All,
+}
+
+/// The selector matched no function in the template, or more than one.
+#[derive(Debug)]
+pub struct SelectionError(String);
+
+impl fmt::Display for SelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
+}
 
-    let env_properties = resources[0]
-        .get("Properties")
-        .and_then(|p| p.get("Environment"))
-        .and_then(|e| e.get("Variables"))
-        .and_then(|v| v.as_mapping())
-        .map(|v| {
-            v.keys()
-                .map(|k| {
-                    k.as_str()
-                        .expect("Environment Variable key should be a string")
-                })
-                .collect::<Vec<_>>()
-        });
+impl std::error::Error for SelectionError {}
 
-    eprintln!("{:#?}", env_properties);
+impl FunctionEnv {
+    /// Loads the environment of every function matching the selector,
+    /// failing if there's none.
+    pub fn load_all(template_path: &Path, selector: &FunctionSelector) -> Result<Vec<Self>> {
+        let template = std::fs::read(template_path).map_err(|err| {
+            anyhow::anyhow!("Failed to read template file {:?}: {}", template_path, err)
+        })?;
+        let template = Template::from_slice(&template)?;
+        let functions = template.functions()?;
 
-    let props = env_properties
-        .map(|v| {
-            let v: Vec<_> = v
+        let matches: Vec<&Function> = match selector {
+            FunctionSelector::LogicalId(logical_id) => functions
+                .iter()
+                .filter(|f| f.logical_id == logical_id)
+                .collect(),
+            FunctionSelector::Package { package, binary } => {
+                let binary = binary.as_ref().unwrap_or(package);
+                let by_metadata = |key: &str, sub_key: &str, expected: &str| {
+                    functions
+                        .iter()
+                        .filter(|f| {
+                            f.resource
+                                .get("Metadata")
+                                .and_then(|m| m.get(key))
+                                .and_then(|m| m.get(sub_key))
+                                .and_then(|v| v.as_str())
+                                == Some(expected)
+                        })
+                        .collect::<Vec<_>>()
+                };
+                let by_code_uri = || {
+                    functions
+                        .iter()
+                        .filter(|f| {
+                            f.code_uri
+                                .map(|c| {
+                                    c.trim_end_matches('/').ends_with(&format!("/{}", package))
+                                })
+                                .unwrap_or(false)
+                        })
+                        .collect::<Vec<_>>()
+                };
+
+                [
+                    by_metadata("SamEnv", "Package", package),
+                    by_metadata("BuildProperties", "Binary", binary),
+                ]
                 .into_iter()
-                .map(|k| {
-                    format!(
-                        r##"
-#[envconfig(from = "{}")]
-{}: String,"##,
-                        k,
-                        k.to_lowercase()
-                    )
+                .find(|matches| !matches.is_empty())
+                .unwrap_or_else(by_code_uri)
+            }
+            FunctionSelector::All => functions.iter().collect(),
+        };
+
+        if matches.is_empty() {
+            let available = functions
+                .iter()
+                .map(|f| f.logical_id)
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(SelectionError(match selector {
+                FunctionSelector::LogicalId(logical_id) => format!(
+                    "No function with logical ID {} found in the template. Available: {}",
+                    logical_id, available
+                ),
+                FunctionSelector::Package { package, .. } => format!(
+                    "No function found for package {} (by Metadata.SamEnv.Package, Metadata.BuildProperties.Binary or CodeUri). Available: {}",
+                    package, available
+                ),
+                FunctionSelector::All => "No function found in the template".to_string(),
+            })
+            .into());
+        }
+
+        matches
+            .into_iter()
+            .map(|function| {
+                let variables_metadata =
+                    function.sam_env_metadata().and_then(|s| s.get("Variables"));
+
+                let variables = function
+                    .environment
+                    .iter()
+                    .map(|(k, v)| Variable::new(k, v, variables_metadata.and_then(|m| m.get(k))))
+                    .collect::<Result<Vec<_>>>()?;
+
+                // variables differing only in case would generate the same field
+                for (i, variable) in variables.iter().enumerate() {
+                    if let Some(other) = variables[..i]
+                        .iter()
+                        .find(|v| v.field_name() == variable.field_name())
+                    {
+                        return Err(anyhow::anyhow!(
+                            "Environment variables {} and {} of {} would both generate the field {}",
+                            other.name,
+                            variable.name,
+                            function.logical_id,
+                            variable.field_name()
+                        ));
+                    }
+                }
+
+                Ok(Self {
+                    template_path: template_path.to_path_buf(),
+                    logical_id: function.logical_id.to_string(),
+                    code_uri: function.code_uri.map(|c| c.to_string()),
+                    policies: function.policies.clone(),
+                    variables,
                 })
-                .collect();
-            v.join("\n")
-        })
-        .unwrap_or("".to_string());
+            })
+            .collect()
+    }
 
-    let output = format!(
-        r#"
-use envconfig::Envconfig;
+    pub fn logical_id(&self) -> &str {
+        &self.logical_id
+    }
 
-#[derive(Debug, Envconfig)]
-pub struct {} {{
-    {}
-}}"#,
-        config.struct_name, props
-    );
+    /// The local directory the function is built from, relative to the
+    /// template.
+    pub fn code_uri(&self) -> Option<&str> {
+        self.code_uri.as_deref()
+    }
 
-    eprintln!("{}", output);
+    /// Checks the `Policies` of the function against the AWS SDK clients the
+    /// crate in `crate_dir` depends on and the operations it calls (matched
+    /// by name in its code, comments and strings aside). Only the actions are
+    /// checked, not the resources they're granted on. Returns `None` for
+    /// lambda functions, whose permissions come from a role.
+    pub fn check_policies(&self, crate_dir: &Path) -> Result<Option<PolicyReport>> {
+        let Some(policies) = &self.policies else {
+            return Ok(None);
+        };
+        let secrets: Vec<_> = self
+            .variables
+            .iter()
+            .filter(|v| v.secret)
+            .map(|v| (v.name.as_str(), v.value.literal_prefix()))
+            .collect();
+        policies::check(&self.logical_id, policies, crate_dir, &secrets).map(Some)
+    }
 
-    let out_dir = config.output_path;
-    let dest_path = std::path::Path::new(&out_dir).join(config.output_filename);
-    std::fs::write(&dest_path, output)?;
+    /// The Rust code of the struct (and its helpers) holding the environment.
+    pub fn generate_struct(&self, decl: &StructDecl) -> String {
+        codegen::generate(decl, &self.variables)
+    }
 
-    Ok(())
+    /// A `.env` file listing the variables of the function, with their
+    /// literal values or the expression to resolve by hand.
+    pub fn env_example(&self) -> String {
+        let header = format!(
+            "Generated by sam_env from {} ({})",
+            self.template_path.display(),
+            self.logical_id
+        );
+        codegen::env_example(&header, &self.variables)
+    }
 }
@@ -0,0 +1,91 @@
+use anyhow::{anyhow, Result};
+use sam_env::{FunctionEnv, FunctionSelector};
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage:
+  sam_env env-example [--template <path>] (--function <logical id> | --package <name>)
+  sam_env check [--template <path>] [--function <logical id> | --package <name>]";
+
+struct Args {
+    command: String,
+    template_path: PathBuf,
+    selector: Option<FunctionSelector>,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = std::env::args().skip(1);
+    let command = args.next().ok_or_else(|| anyhow!(USAGE))?;
+    let mut template_path = PathBuf::from("template.yaml");
+    let mut selector = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--template" => template_path = value()?.into(),
+            "--function" => selector = Some(FunctionSelector::LogicalId(value()?)),
+            "--package" => {
+                selector = Some(FunctionSelector::Package {
+                    package: value()?,
+                    binary: None,
+                })
+            }
+            _ => return Err(anyhow!("Unknown argument {}\n{}", arg, USAGE)),
+        }
+    }
+
+    Ok(Args {
+        command,
+        template_path,
+        selector,
+    })
+}
+
+fn main() -> Result<()> {
+    let args = parse_args()?;
+
+    match args.command.as_str() {
+        "env-example" => {
+            let selector = args.selector.ok_or_else(|| anyhow!(USAGE))?;
+            let function_envs = FunctionEnv::load_all(&args.template_path, &selector)?;
+            let examples: Vec<_> = function_envs.iter().map(|f| f.env_example()).collect();
+            print!("{}", examples.join("\n"));
+        }
+        "check" => {
+            let selector = args.selector.unwrap_or(FunctionSelector::All);
+            let function_envs = FunctionEnv::load_all(&args.template_path, &selector)?;
+            let template_dir = args.template_path.parent().unwrap_or(Path::new(""));
+            let mut failed = vec![];
+            for function_env in &function_envs {
+                // only the functions built from a local cargo package
+                let Some(crate_dir) = function_env
+                    .code_uri()
+                    .map(|c| template_dir.join(c))
+                    .filter(|dir| dir.join("Cargo.toml").is_file())
+                else {
+                    continue;
+                };
+                match function_env.check_policies(&crate_dir)? {
+                    Some(report) => {
+                        println!("{}", report);
+                        if !report.is_ok() {
+                            failed.push(report.logical_id);
+                        }
+                    }
+                    None => println!(
+                        "{}\n  skipped: the permissions of AWS::Lambda::Function come from its Role\n",
+                        function_env.logical_id()
+                    ),
+                }
+            }
+            if !failed.is_empty() {
+                return Err(anyhow!("Missing permissions in {}", failed.join(", ")));
+            }
+        }
+        _ => return Err(anyhow!(USAGE)),
+    }
+
+    Ok(())
+}
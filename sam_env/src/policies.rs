@@ -0,0 +1,561 @@
+use anyhow::{anyhow, Result};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// A policy attached to a function through its `Policies` property.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Policy {
+    /// A SAM policy template, e.g. `DynamoDBReadPolicy`
+    Template(String),
+    /// A managed policy name or ARN, whose content isn't inspected
+    Managed(String),
+    /// An inline policy document, with the actions it allows
+    Statements(Vec<String>),
+}
+
+impl Policy {
+    /// Parses the `Policies` property of a serverless function.
+    pub fn parse_all(policies: &Value) -> Vec<Self> {
+        match policies {
+            Value::Sequence(policies) => policies.iter().filter_map(Self::parse).collect(),
+            policy => Self::parse(policy).into_iter().collect(),
+        }
+    }
+
+    fn parse(policy: &Value) -> Option<Self> {
+        match policy {
+            Value::String(name) => Some(Policy::Managed(name.clone())),
+            Value::Mapping(mapping) => match mapping.get("Statement") {
+                Some(statements) => Some(Policy::Statements(allowed_actions(statements))),
+                None => mapping
+                    .keys()
+                    .next()
+                    .and_then(|k| k.as_str())
+                    .map(|k| Policy::Template(k.to_string())),
+            },
+            _ => None,
+        }
+    }
+
+    fn grants(&self, action: &str) -> bool {
+        match self {
+            Policy::Template(name) => POLICY_TEMPLATES
+                .iter()
+                .find(|(template, _)| template == name)
+                .map(|(_, actions)| actions.contains(&action))
+                .unwrap_or(false),
+            Policy::Managed(_) => false,
+            Policy::Statements(actions) => actions.iter().any(|allowed| {
+                allowed == "*"
+                    || allowed == action
+                    || allowed
+                        .strip_suffix('*')
+                        .map(|prefix| action.starts_with(prefix))
+                        .unwrap_or(false)
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Policy::Template(name) | Policy::Managed(name) => write!(f, "{}", name),
+            Policy::Statements(actions) => write!(f, "inline policy ({})", actions.join(", ")),
+        }
+    }
+}
+
+fn allowed_actions(statements: &Value) -> Vec<String> {
+    let statements = match statements {
+        Value::Sequence(statements) => statements.iter().collect(),
+        statement => vec![statement],
+    };
+    statements
+        .into_iter()
+        .filter(|s| s.get("Effect").and_then(|e| e.as_str()) == Some("Allow"))
+        .filter_map(|s| s.get("Action"))
+        .flat_map(|actions| match actions {
+            Value::Sequence(actions) => actions.iter().filter_map(|a| a.as_str()).collect(),
+            action => action.as_str().into_iter().collect::<Vec<_>>(),
+        })
+        .map(|a| a.to_string())
+        .collect()
+}
+
+/// The actions granted by the SAM policy templates, limited to the ones
+/// checked here.
+const POLICY_TEMPLATES: &[(&str, &[&str])] = &[
+    (
+        "DynamoDBReadPolicy",
+        &[
+            "dynamodb:GetItem",
+            "dynamodb:Scan",
+            "dynamodb:Query",
+            "dynamodb:BatchGetItem",
+            "dynamodb:DescribeTable",
+            "dynamodb:ConditionCheckItem",
+        ],
+    ),
+    (
+        "DynamoDBWritePolicy",
+        &[
+            "dynamodb:PutItem",
+            "dynamodb:UpdateItem",
+            "dynamodb:BatchWriteItem",
+        ],
+    ),
+    (
+        "DynamoDBCrudPolicy",
+        &[
+            "dynamodb:GetItem",
+            "dynamodb:DeleteItem",
+            "dynamodb:PutItem",
+            "dynamodb:Scan",
+            "dynamodb:Query",
+            "dynamodb:UpdateItem",
+            "dynamodb:BatchWriteItem",
+            "dynamodb:BatchGetItem",
+            "dynamodb:DescribeTable",
+            "dynamodb:ConditionCheckItem",
+        ],
+    ),
+    ("SQSSendMessagePolicy", &["sqs:SendMessage"]),
+    (
+        "SQSPollerPolicy",
+        &[
+            "sqs:ChangeMessageVisibility",
+            "sqs:DeleteMessage",
+            "sqs:GetQueueAttributes",
+            "sqs:ReceiveMessage",
+        ],
+    ),
+    (
+        "S3ReadPolicy",
+        &["s3:GetObject", "s3:ListBucket", "s3:GetBucketLocation"],
+    ),
+    ("S3WritePolicy", &["s3:PutObject", "s3:PutObjectAcl"]),
+    (
+        "S3CrudPolicy",
+        &[
+            "s3:GetObject",
+            "s3:ListBucket",
+            "s3:GetBucketLocation",
+            "s3:PutObject",
+            "s3:PutObjectAcl",
+            "s3:DeleteObject",
+        ],
+    ),
+    (
+        "SESCrudPolicy",
+        &[
+            "ses:GetIdentityVerificationAttributes",
+            "ses:SendEmail",
+            "ses:SendRawEmail",
+            "ses:VerifyEmailIdentity",
+        ],
+    ),
+    (
+        "SESBulkTemplatedCrudPolicy",
+        &[
+            "ses:GetIdentityVerificationAttributes",
+            "ses:SendEmail",
+            "ses:SendRawEmail",
+            "ses:SendTemplatedEmail",
+            "ses:SendBulkTemplatedEmail",
+            "ses:VerifyEmailIdentity",
+        ],
+    ),
+    (
+        "SESBulkTemplatedCrudPolicy_v2",
+        &[
+            "ses:SendEmail",
+            "ses:SendTemplatedEmail",
+            "ses:SendBulkTemplatedEmail",
+        ],
+    ),
+    (
+        "SSMParameterReadPolicy",
+        &[
+            "ssm:DescribeParameters",
+            "ssm:GetParameters",
+            "ssm:GetParameter",
+            "ssm:GetParametersByPath",
+        ],
+    ),
+    (
+        "SSMParameterWithSlashPrefixReadPolicy",
+        &[
+            "ssm:DescribeParameters",
+            "ssm:GetParameters",
+            "ssm:GetParameter",
+            "ssm:GetParametersByPath",
+        ],
+    ),
+    (
+        "AWSSecretsManagerGetSecretValuePolicy",
+        &["secretsmanager:GetSecretValue"],
+    ),
+    ("SNSPublishMessagePolicy", &["sns:Publish"]),
+    ("LambdaInvokePolicy", &["lambda:InvokeFunction"]),
+    ("EventBridgePutEventsPolicy", &["events:PutEvents"]),
+];
+
+/// A client operation looked for in the sources, with the action it needs.
+type Operation = (&'static str, &'static str);
+
+/// For every AWS SDK crate: the IAM service prefix and its operations.
+const SDK_OPERATIONS: &[(&str, &str, &[Operation])] = &[
+    (
+        "aws-sdk-dynamodb",
+        "dynamodb",
+        &[
+            ("get_item", "GetItem"),
+            ("query", "Query"),
+            ("scan", "Scan"),
+            ("batch_get_item", "BatchGetItem"),
+            ("put_item", "PutItem"),
+            ("update_item", "UpdateItem"),
+            ("delete_item", "DeleteItem"),
+            ("batch_write_item", "BatchWriteItem"),
+        ],
+    ),
+    (
+        "aws-sdk-sqs",
+        "sqs",
+        &[
+            ("send_message", "SendMessage"),
+            ("send_message_batch", "SendMessage"),
+            ("receive_message", "ReceiveMessage"),
+            ("delete_message", "DeleteMessage"),
+            ("change_message_visibility", "ChangeMessageVisibility"),
+        ],
+    ),
+    (
+        "aws-sdk-s3",
+        "s3",
+        &[
+            ("get_object", "GetObject"),
+            ("head_object", "GetObject"),
+            ("put_object", "PutObject"),
+            ("delete_object", "DeleteObject"),
+            ("list_objects_v2", "ListBucket"),
+        ],
+    ),
+    (
+        "aws-sdk-ses",
+        "ses",
+        &[
+            ("send_email", "SendEmail"),
+            ("send_raw_email", "SendRawEmail"),
+            ("send_templated_email", "SendTemplatedEmail"),
+        ],
+    ),
+    (
+        "aws-sdk-sesv2",
+        "ses",
+        &[
+            ("send_email", "SendEmail"),
+            ("get_email_identity", "GetEmailIdentity"),
+        ],
+    ),
+    ("aws-sdk-ssm", "ssm", &[("get_parameter", "GetParameter")]),
+    (
+        "aws-sdk-secretsmanager",
+        "secretsmanager",
+        &[("get_secret_value", "GetSecretValue")],
+    ),
+    ("aws-sdk-sns", "sns", &[("publish", "Publish")]),
+    ("aws-sdk-lambda", "lambda", &[("invoke", "InvokeFunction")]),
+    (
+        "aws-sdk-scheduler",
+        "scheduler",
+        &[
+            ("create_schedule", "CreateSchedule"),
+            ("delete_schedule", "DeleteSchedule"),
+        ],
+    ),
+];
+
+/// An IAM action the function needs, and the reasons why.
+#[derive(Debug)]
+pub struct Requirement {
+    pub action: String,
+    pub reasons: Vec<String>,
+    /// The attached policy granting the action, if any
+    pub granted_by: Option<String>,
+}
+
+/// The outcome of checking the policies of a function against the AWS SDK
+/// clients its crate depends on and the operations its sources call.
+#[derive(Debug)]
+pub struct PolicyReport {
+    pub logical_id: String,
+    pub policies: Vec<Policy>,
+    pub requirements: Vec<Requirement>,
+}
+
+impl PolicyReport {
+    pub fn missing(&self) -> impl Iterator<Item = &Requirement> {
+        self.requirements.iter().filter(|r| r.granted_by.is_none())
+    }
+
+    /// The policy templates granting none of the actions the function needs.
+    /// Reported without failing, they might be needed by code that isn't
+    /// matched (or be left over).
+    pub fn unused(&self) -> impl Iterator<Item = &Policy> {
+        self.policies.iter().filter(|p| {
+            matches!(p, Policy::Template(name) if POLICY_TEMPLATES.iter().any(|(t, _)| t == name))
+                && !self.requirements.iter().any(|r| p.grants(&r.action))
+        })
+    }
+
+    /// With managed policies attached, missing actions might still be
+    /// granted, so they are reported without failing.
+    pub fn is_conclusive(&self) -> bool {
+        !self
+            .policies
+            .iter()
+            .any(|p| matches!(p, Policy::Managed(_)))
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.missing().next().is_none() || !self.is_conclusive()
+    }
+}
+
+impl fmt::Display for PolicyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.logical_id)?;
+        let policies: Vec<_> = self.policies.iter().map(|p| p.to_string()).collect();
+        writeln!(f, "  policies: {}", policies.join(", "))?;
+        for requirement in &self.requirements {
+            let status = match (&requirement.granted_by, self.is_conclusive()) {
+                (Some(policy), _) => format!("ok ({})", policy),
+                (None, true) => "MISSING".to_string(),
+                (None, false) => "unverified (managed policies attached)".to_string(),
+            };
+            writeln!(
+                f,
+                "  {:<32} {} <- {}",
+                requirement.action,
+                status,
+                requirement.reasons.join(", ")
+            )?;
+            if requirement.granted_by.is_none() {
+                let templates = templates_granting(&requirement.action);
+                if !templates.is_empty() {
+                    writeln!(f, "  {:<32} add one of: {}", "", templates.join(", "))?;
+                }
+            }
+        }
+        let unused: Vec<_> = self.unused().map(|p| p.to_string()).collect();
+        if !unused.is_empty() {
+            writeln!(f, "  unused: {}", unused.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn templates_granting(action: &str) -> Vec<&'static str> {
+    POLICY_TEMPLATES
+        .iter()
+        .filter(|(_, actions)| actions.contains(&action))
+        .map(|(template, _)| *template)
+        .collect()
+}
+
+/// Checks the policies of a function against the crate in `crate_dir`.
+/// `secrets` lists the secret variables, with the start of their reference,
+/// to add the permissions to read the secrets resolved by the generated code.
+pub fn check(
+    logical_id: &str,
+    policies: &[Policy],
+    crate_dir: &Path,
+    secrets: &[(&str, &str)],
+) -> Result<PolicyReport> {
+    let manifest_path = crate_dir.join("Cargo.toml");
+    let manifest = std::fs::read_to_string(&manifest_path)
+        .map_err(|err| anyhow!("Failed to read {:?}: {}", manifest_path, err))?;
+    let manifest: toml::Table = manifest
+        .parse()
+        .map_err(|err| anyhow!("Failed to parse {:?}: {}", manifest_path, err))?;
+    let dependencies = manifest
+        .get("dependencies")
+        .and_then(|d| d.as_table())
+        .cloned()
+        .unwrap_or_default();
+
+    let mut sources = vec![];
+    read_sources(&crate_dir.join("src"), crate_dir, &mut sources)?;
+    let sources: Vec<_> = sources
+        .into_iter()
+        .map(|(path, source)| (path, code_only(&source)))
+        .collect();
+
+    // action -> reasons
+    let mut needed: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (sdk, service, operations) in SDK_OPERATIONS {
+        if !dependencies.contains_key(*sdk) {
+            continue;
+        }
+        for (operation, action) in *operations {
+            for (path, source) in &sources {
+                if calls(source, operation) {
+                    needed
+                        .entry(format!("{}:{}", service, action))
+                        .or_default()
+                        .push(format!("{} in {}", operation, path));
+                }
+            }
+        }
+    }
+
+    for (name, reference) in secrets {
+        let action = if reference.starts_with("ssm:") || reference.starts_with("arn:aws:ssm:") {
+            "ssm:GetParameter"
+        } else if reference.starts_with("secretsmanager:")
+            || reference.starts_with("arn:aws:secretsmanager:")
+        {
+            "secretsmanager:GetSecretValue"
+        } else {
+            // only known once deployed, the generated code supports both
+            ["ssm:GetParameter", "secretsmanager:GetSecretValue"]
+                .into_iter()
+                .find(|action| policies.iter().any(|p| p.grants(action)))
+                .unwrap_or("ssm:GetParameter")
+        };
+        needed
+            .entry(action.to_string())
+            .or_default()
+            .push(format!("secret {}", name));
+    }
+
+    let requirements = needed
+        .into_iter()
+        .map(|(action, reasons)| Requirement {
+            granted_by: policies
+                .iter()
+                .find(|p| p.grants(&action))
+                .map(|p| p.to_string()),
+            action,
+            reasons,
+        })
+        .collect();
+
+    Ok(PolicyReport {
+        logical_id: logical_id.to_string(),
+        policies: policies.to_vec(),
+        requirements,
+    })
+}
+
+fn read_sources(dir: &Path, crate_dir: &Path, sources: &mut Vec<(String, String)>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            read_sources(&path, crate_dir, sources)?;
+        } else if path.extension().map(|e| e == "rs").unwrap_or(false) {
+            let relative = path.strip_prefix(crate_dir).unwrap_or(&path);
+            sources.push((
+                relative.display().to_string(),
+                std::fs::read_to_string(&path)?,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `source` calls the method `operation` without arguments, as done
+/// to build a request with the fluent builders of the SDK clients.
+fn calls(source: &str, operation: &str) -> bool {
+    source.match_indices('.').any(|(i, _)| {
+        let rest = source[i + 1..].trim_start();
+        rest.strip_prefix(operation)
+            .map(|rest| rest.trim_start())
+            .and_then(|rest| rest.strip_prefix('('))
+            .map(|rest| rest.trim_start().starts_with(')'))
+            .unwrap_or(false)
+    })
+}
+
+/// The source with its comments, string and char literals blanked out, so
+/// that only the code is matched.
+fn code_only(source: &str) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let mut code = String::with_capacity(source.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let end = if c == '/' && next == Some('/') {
+            chars[i..]
+                .iter()
+                .position(|&c| c == '\n')
+                .map_or(chars.len(), |n| i + n)
+        } else if c == '/' && next == Some('*') {
+            let mut depth = 0;
+            let mut j = i;
+            while j < chars.len() {
+                match (chars[j], chars.get(j + 1)) {
+                    ('/', Some('*')) => {
+                        depth += 1;
+                        j += 2;
+                    }
+                    ('*', Some('/')) => {
+                        depth -= 1;
+                        j += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => j += 1,
+                }
+            }
+            j
+        } else if let Some(closing) = raw_string_closing(&chars, i) {
+            let start = i + closing.len() + 1;
+            (start..chars.len())
+                .find(|&j| chars[j..].starts_with(&closing))
+                .map_or(chars.len(), |j| j + closing.len())
+        } else if c == '"' {
+            let mut j = i + 1;
+            while j < chars.len() && chars[j] != '"' {
+                j += if chars[j] == '\\' { 2 } else { 1 };
+            }
+            j + 1
+        } else if c == '\'' && next == Some('\\') {
+            // an escaped char literal, e.g. '\n' or '\''
+            (i + 3..chars.len())
+                .find(|&j| chars[j] == '\'')
+                .map_or(chars.len(), |j| j + 1)
+        } else if c == '\'' && chars.get(i + 2) == Some(&'\'') {
+            i + 3
+        } else {
+            // a lifetime, or anything else
+            code.push(c);
+            i += 1;
+            continue;
+        };
+        code.push(' ');
+        i = end.min(chars.len());
+    }
+    code
+}
+
+/// The end of the raw string literal starting at `i` (`"` followed by as
+/// many `#` as it starts with), if any.
+fn raw_string_closing(chars: &[char], i: usize) -> Option<Vec<char>> {
+    let after_ident = i > 0 && (chars[i - 1].is_alphanumeric() || chars[i - 1] == '_');
+    if chars[i] != 'r' || after_ident {
+        return None;
+    }
+    let hashes = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+    (chars.get(i + 1 + hashes) == Some(&'"')).then(|| {
+        std::iter::once('"')
+            .chain(std::iter::repeat_n('#', hashes))
+            .collect()
+    })
+}
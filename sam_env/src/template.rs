@@ -0,0 +1,131 @@
+use crate::policies::Policy;
+use anyhow::{anyhow, Result};
+use serde_yaml::Value;
+
+const SERVERLESS_FUNCTION: &str = "AWS::Serverless::Function";
+const LAMBDA_FUNCTION: &str = "AWS::Lambda::Function";
+
+/// A parsed SAM (or plain CloudFormation) template.
+pub struct Template {
+    value: Value,
+}
+
+/// A function declared in the template, either an `AWS::Serverless::Function`
+/// (with the `Globals.Function` section merged in) or an `AWS::Lambda::Function`.
+#[derive(Debug)]
+pub struct Function<'a> {
+    pub logical_id: &'a str,
+    pub resource: &'a Value,
+    /// `CodeUri` for serverless functions, `Code` for lambda functions, when
+    /// it's a local path
+    pub code_uri: Option<&'a str>,
+    /// Environment variables and their (unresolved) values, globals first
+    pub environment: Vec<(&'a str, &'a Value)>,
+    /// The `Policies` of serverless functions, `None` for lambda functions
+    /// (whose `Role` isn't inspected)
+    pub policies: Option<Vec<Policy>>,
+}
+
+impl Template {
+    pub fn from_slice(template: &[u8]) -> Result<Self> {
+        let value = serde_yaml::from_slice(template)
+            .map_err(|err| anyhow!("Failed to parse yaml template file: {}", err))?;
+        Ok(Self { value })
+    }
+
+    pub fn functions(&self) -> Result<Vec<Function<'_>>> {
+        let resources = self
+            .value
+            .get("Resources")
+            .ok_or_else(|| anyhow!("The template has no Resources section"))?
+            .as_mapping()
+            .ok_or_else(|| anyhow!("The Resources section of the template should be a mapping"))?;
+
+        let globals = self.value.get("Globals").and_then(|g| g.get("Function"));
+
+        let mut functions = vec![];
+        for (logical_id, resource) in resources {
+            let logical_id = logical_id
+                .as_str()
+                .ok_or_else(|| anyhow!("Resource logical IDs should be strings"))?;
+            let kind = resource.get("Type").and_then(|t| t.as_str());
+            let properties = resource.get("Properties");
+
+            let (code_uri, environment, policies) = match kind {
+                Some(SERVERLESS_FUNCTION) => {
+                    let code_uri = properties
+                        .and_then(|p| p.get("CodeUri"))
+                        .or_else(|| globals.and_then(|g| g.get("CodeUri")))
+                        .and_then(|c| c.as_str());
+                    let mut environment = environment_variables("Globals.Function", globals)?;
+                    for (key, value) in environment_variables(logical_id, properties)? {
+                        match environment.iter_mut().find(|(k, _)| *k == key) {
+                            Some(global) => global.1 = value,
+                            None => environment.push((key, value)),
+                        }
+                    }
+                    let policies = properties
+                        .and_then(|p| p.get("Policies"))
+                        .map(Policy::parse_all)
+                        .unwrap_or_default();
+                    (code_uri, environment, Some(policies))
+                }
+                Some(LAMBDA_FUNCTION) => (
+                    properties
+                        .and_then(|p| p.get("Code"))
+                        .and_then(|c| c.as_str()),
+                    environment_variables(logical_id, properties)?,
+                    None,
+                ),
+                _ => continue,
+            };
+
+            functions.push(Function {
+                logical_id,
+                resource,
+                code_uri,
+                environment,
+                policies,
+            });
+        }
+
+        Ok(functions)
+    }
+}
+
+impl<'a> Function<'a> {
+    /// The `Metadata.SamEnv` block of the function, if any.
+    pub fn sam_env_metadata(&self) -> Option<&'a Value> {
+        self.resource.get("Metadata").and_then(|m| m.get("SamEnv"))
+    }
+}
+
+/// The `Environment.Variables` of a function `Properties` (or of the
+/// `Globals.Function` section).
+fn environment_variables<'a>(
+    owner: &str,
+    properties: Option<&'a Value>,
+) -> Result<Vec<(&'a str, &'a Value)>> {
+    let Some(variables) = properties
+        .and_then(|p| p.get("Environment"))
+        .and_then(|e| e.get("Variables"))
+    else {
+        return Ok(vec![]);
+    };
+    let variables = variables
+        .as_mapping()
+        .ok_or_else(|| anyhow!("The environment variables of {} should be a mapping", owner))?;
+    variables
+        .iter()
+        .map(|(k, v)| {
+            let key = k.as_str().ok_or_else(|| {
+                anyhow!(
+                    "The environment variable names of {} should be strings, found {:?}",
+                    owner,
+                    k
+                )
+            })?;
+            Ok((key, v))
+        })
+        .collect()
+}
@@ -0,0 +1,159 @@
+use crate::intrinsics::Expr;
+use anyhow::{anyhow, Result};
+use serde_yaml::Value;
+
+/// Environment variables whose name ends with this suffix are treated as
+/// secrets, as well as the ones marked with `Secret: true` in the function
+/// `Metadata.SamEnv.Variables` block.
+const SECRET_SUFFIX: &str = "_SECRET";
+
+/// The type of an environment variable, declared with `Type:` in the function
+/// `Metadata.SamEnv.Variables` block. Variables without a declared type are
+/// strings.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VarType {
+    String,
+    U64,
+    I64,
+    U32,
+    Bool,
+    /// Seconds (`86400`) or a number with a unit (`500ms`, `60s`, `15m`, `24h`, `7d`)
+    Duration,
+    Url,
+    /// Comma-separated values, `List` alone is a list of strings
+    List(Box<VarType>),
+}
+
+impl VarType {
+    pub fn parse(declared: &str) -> Result<Self> {
+        let declared = declared.trim();
+        if let Some(inner) = declared
+            .strip_prefix("List<")
+            .and_then(|t| t.strip_suffix('>'))
+        {
+            let inner = Self::parse(inner)?;
+            if matches!(inner, VarType::List(_)) {
+                return Err(anyhow!("Nested lists are not supported: {}", declared));
+            }
+            return Ok(VarType::List(Box::new(inner)));
+        }
+
+        match declared {
+            "String" => Ok(VarType::String),
+            "u64" => Ok(VarType::U64),
+            "i64" => Ok(VarType::I64),
+            "u32" => Ok(VarType::U32),
+            "bool" => Ok(VarType::Bool),
+            "Duration" => Ok(VarType::Duration),
+            "Url" => Ok(VarType::Url),
+            "List" => Ok(VarType::List(Box::new(VarType::String))),
+            _ => Err(anyhow!("Unsupported variable type: {}", declared)),
+        }
+    }
+
+    /// The Rust type of the generated struct field.
+    pub fn rust_type(&self) -> String {
+        match self {
+            VarType::String => "String".to_string(),
+            VarType::U64 => "u64".to_string(),
+            VarType::I64 => "i64".to_string(),
+            VarType::U32 => "u32".to_string(),
+            VarType::Bool => "bool".to_string(),
+            VarType::Duration => "types::Duration".to_string(),
+            VarType::Url => "url::Url".to_string(),
+            VarType::List(inner) => format!("types::List<{}>", inner.rust_type()),
+        }
+    }
+
+    /// A human readable description of the expected format, used in startup errors.
+    pub fn expected(&self) -> String {
+        match self {
+            VarType::String => "a string".to_string(),
+            VarType::U64 | VarType::U32 => "a non-negative integer".to_string(),
+            VarType::I64 => "an integer".to_string(),
+            VarType::Bool => "true or false".to_string(),
+            VarType::Duration => "a duration (e.g. 86400, 500ms, 60s, 15m, 24h, 7d)".to_string(),
+            VarType::Url => "an absolute URL".to_string(),
+            VarType::List(inner) => format!("a comma-separated list of {}", inner.expected()),
+        }
+    }
+
+    pub fn needs_helpers(&self) -> bool {
+        matches!(self, VarType::Duration | VarType::List(_))
+    }
+}
+
+#[derive(Debug)]
+pub struct Variable {
+    pub name: String,
+    pub value: Expr,
+    pub var_type: VarType,
+    pub secret: bool,
+    pub default: Option<String>,
+    /// Generates an `Option<T>`. With a default, the variable defaults to it
+    /// when it is not set and is `None` when set to an empty value.
+    pub optional: bool,
+}
+
+impl Variable {
+    /// Builds a variable from its name, its value in the template and the
+    /// (optional) entry for it in `Metadata.SamEnv.Variables`.
+    pub fn new(name: &str, value: &Value, metadata: Option<&Value>) -> Result<Self> {
+        let var_type = match metadata.and_then(|m| m.get("Type")) {
+            None => VarType::String,
+            Some(t) => VarType::parse(
+                t.as_str()
+                    .ok_or_else(|| anyhow!("Type of {} should be a string", name))?,
+            )?,
+        };
+
+        let secret = name.ends_with(SECRET_SUFFIX)
+            || metadata
+                .and_then(|m| m.get("Secret"))
+                .and_then(|s| s.as_bool())
+                .unwrap_or(false);
+        if secret && var_type != VarType::String {
+            return Err(anyhow!("Secret variable {} should be a String", name));
+        }
+
+        let default = match metadata.and_then(|m| m.get("Default")) {
+            None => None,
+            Some(Value::String(s)) => Some(s.clone()),
+            Some(Value::Number(n)) => Some(n.to_string()),
+            Some(Value::Bool(b)) => Some(b.to_string()),
+            Some(_) => return Err(anyhow!("Default of {} should be a scalar", name)),
+        };
+
+        let optional = metadata
+            .and_then(|m| m.get("Optional"))
+            .and_then(|o| o.as_bool())
+            .unwrap_or(false);
+
+        Ok(Self {
+            name: name.to_string(),
+            value: Expr::parse(value)?,
+            var_type,
+            secret,
+            default,
+            optional,
+        })
+    }
+
+    pub fn field_name(&self) -> String {
+        self.name.to_lowercase()
+    }
+
+    /// Whether envconfig can't apply the default by itself, the generated
+    /// constructors applying it instead.
+    pub fn optional_default(&self) -> Option<&str> {
+        self.default.as_deref().filter(|_| self.optional)
+    }
+
+    pub fn field_type(&self) -> String {
+        if self.optional {
+            format!("Option<{}>", self.var_type.rust_type())
+        } else {
+            self.var_type.rust_type()
+        }
+    }
+}
@@ -0,0 +1,20 @@
+mod queue;
+
+// calls in comments are ignored: client.delete_item()
+/* and in block comments, /* nested */ too: client.batch_write_item() */
+async fn send(config: &Config) {
+    let subscription = config.dynamodb_client.get_item().send().await;
+    config
+        .dynamodb_client
+        . query ( )
+        .send()
+        .await;
+    config.dynamodb_client.put_item().send().await;
+    config.ses_client.send_email().send().await;
+    // not a client call, it has arguments
+    let total = [1, 2].iter().scan(0, |sum, n| Some(*sum + n));
+    let text = "client.scan()";
+    let raw = r#"client.update_item() "quoted""#;
+    let quote = '"';
+    let escaped = '\'';
+}
@@ -0,0 +1,3 @@
+async fn enqueue<'a>(client: &'a Client) {
+    client.send_message_batch().send().await;
+}
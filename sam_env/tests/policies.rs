@@ -0,0 +1,161 @@
+use sam_env::{FunctionEnv, FunctionSelector, Policy, PolicyReport};
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn check(logical_id: &str) -> Option<PolicyReport> {
+    let function_envs = FunctionEnv::load_all(
+        &fixture("policies.yaml"),
+        &FunctionSelector::LogicalId(logical_id.to_string()),
+    )
+    .expect("functions should load");
+    function_envs[0]
+        .check_policies(&fixture("crates/mailer"))
+        .expect("the crate should be checked")
+}
+
+/// The needed actions, with the policy granting them.
+fn requirements(report: &PolicyReport) -> Vec<(&str, Option<&str>)> {
+    report
+        .requirements
+        .iter()
+        .map(|r| (r.action.as_str(), r.granted_by.as_deref()))
+        .collect()
+}
+
+#[test]
+fn operations_to_actions() {
+    let report = check("MailerFunction").expect("a serverless function");
+    let reasons: Vec<_> = report
+        .requirements
+        .iter()
+        .map(|r| (r.action.as_str(), r.reasons.join(", ")))
+        .collect();
+    // only the calls in code, with any spacing, for the SDK dependencies
+    assert_eq!(
+        reasons,
+        [
+            ("dynamodb:GetItem", "get_item in src/main.rs".to_string()),
+            ("dynamodb:PutItem", "put_item in src/main.rs".to_string()),
+            ("dynamodb:Query", "query in src/main.rs".to_string()),
+            ("ses:SendEmail", "send_email in src/main.rs".to_string()),
+            (
+                "sqs:SendMessage",
+                "send_message_batch in src/queue.rs".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn missing_policies() {
+    let report = check("MailerFunction").expect("a serverless function");
+    assert_eq!(
+        requirements(&report),
+        [
+            ("dynamodb:GetItem", Some("DynamoDBReadPolicy")),
+            // a Deny statement grants nothing
+            ("dynamodb:PutItem", None),
+            ("dynamodb:Query", Some("DynamoDBReadPolicy")),
+            ("ses:SendEmail", Some("inline policy (ses:Send*)")),
+            ("sqs:SendMessage", Some("SQSSendMessagePolicy")),
+        ]
+    );
+    assert!(report.is_conclusive());
+    assert!(!report.is_ok());
+
+    let output = report.to_string();
+    assert!(output.contains(
+        "  dynamodb:PutItem                 MISSING <- put_item in src/main.rs\n\
+        \x20                                  add one of: DynamoDBWritePolicy, DynamoDBCrudPolicy\n"
+    ));
+}
+
+#[test]
+fn unused_policies() {
+    let report = check("MailerFunction").expect("a serverless function");
+    let unused: Vec<_> = report.unused().collect();
+    assert_eq!(unused, [&Policy::Template("S3ReadPolicy".to_string())]);
+    assert!(report.to_string().ends_with("  unused: S3ReadPolicy\n"));
+}
+
+#[test]
+fn managed_policies() {
+    let report = check("ManagedFunction").expect("a serverless function");
+    assert_eq!(
+        report.policies,
+        [
+            Policy::Managed("AWSLambdaBasicExecutionRole".to_string()),
+            Policy::Template("DynamoDBReadPolicy".to_string()),
+        ]
+    );
+    assert_eq!(report.missing().count(), 3);
+    // the managed policy might grant them
+    assert!(!report.is_conclusive());
+    assert!(report.is_ok());
+    assert!(report
+        .to_string()
+        .contains("dynamodb:PutItem                 unverified (managed policies attached)"));
+}
+
+/// The action needed to read `TOKEN_SECRET`, with the policy granting it.
+fn secret_requirement(report: &PolicyReport) -> (&str, Option<&str>) {
+    report
+        .requirements
+        .iter()
+        .find(|r| r.reasons == ["secret TOKEN_SECRET"])
+        .map(|r| (r.action.as_str(), r.granted_by.as_deref()))
+        .expect("the secret should be required")
+}
+
+#[test]
+fn secrets() {
+    let report = check("SsmSecretFunction").expect("a serverless function");
+    assert_eq!(secret_requirement(&report), ("ssm:GetParameter", None));
+    assert!(!report.is_ok());
+
+    let report = check("SecretsManagerSecretFunction").expect("a serverless function");
+    assert_eq!(
+        secret_requirement(&report),
+        (
+            "secretsmanager:GetSecretValue",
+            Some("AWSSecretsManagerGetSecretValuePolicy")
+        )
+    );
+    let missing: Vec<_> = report.missing().map(|r| r.action.as_str()).collect();
+    assert_eq!(missing, ["ses:SendEmail", "sqs:SendMessage"]);
+}
+
+#[test]
+fn secret_in_another_store() {
+    let report = check("WrongStoreFunction").expect("a serverless function");
+    assert_eq!(
+        secret_requirement(&report),
+        ("secretsmanager:GetSecretValue", None)
+    );
+    assert!(report
+        .to_string()
+        .contains("add one of: AWSSecretsManagerGetSecretValuePolicy\n"));
+}
+
+#[test]
+fn secret_known_once_deployed() {
+    // either store is enough
+    let report = check("ParameterSecretFunction").expect("a serverless function");
+    assert_eq!(
+        secret_requirement(&report),
+        (
+            "secretsmanager:GetSecretValue",
+            Some("AWSSecretsManagerGetSecretValuePolicy")
+        )
+    );
+}
+
+#[test]
+fn lambda_function() {
+    assert!(check("RoleFunction").is_none());
+}
@@ -0,0 +1,188 @@
+use sam_env::{FunctionEnv, FunctionSelector, SelectionError, StructDecl};
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn logical_id(id: &str) -> FunctionSelector {
+    FunctionSelector::LogicalId(id.to_string())
+}
+
+fn package(name: &str) -> FunctionSelector {
+    FunctionSelector::Package {
+        package: name.to_string(),
+        binary: None,
+    }
+}
+
+fn load_error(name: &str, selector: &FunctionSelector) -> anyhow::Error {
+    FunctionEnv::load_all(&fixture(name), selector).expect_err("loading should fail")
+}
+
+fn generate(function_env: &FunctionEnv) -> String {
+    function_env.generate_struct(&StructDecl {
+        attributes: "",
+        visibility: "",
+        name: "SamEnv",
+    })
+}
+
+#[test]
+fn missing_resources() {
+    let err = load_error("no_resources.yaml", &logical_id("SubscribeFunction"));
+    assert_eq!(err.to_string(), "The template has no Resources section");
+}
+
+#[test]
+fn resources_not_a_mapping() {
+    let err = load_error(
+        "resources_not_mapping.yaml",
+        &logical_id("SubscribeFunction"),
+    );
+    assert_eq!(
+        err.to_string(),
+        "The Resources section of the template should be a mapping"
+    );
+}
+
+#[test]
+fn missing_template() {
+    let err = load_error("missing.yaml", &logical_id("SubscribeFunction"));
+    assert!(err.to_string().starts_with("Failed to read template file"));
+}
+
+#[test]
+fn no_matching_logical_id() {
+    let err = load_error("functions.yaml", &logical_id("UnsubscribeFunction"));
+    assert!(err.downcast_ref::<SelectionError>().is_some());
+    assert_eq!(
+        err.to_string(),
+        "No function with logical ID UnsubscribeFunction found in the template. \
+         Available: SubscribeHttpFunction, SubscribeQueueFunction, NoEnvironmentFunction"
+    );
+}
+
+#[test]
+fn no_matching_package() {
+    let err = load_error("functions.yaml", &package("unsubscribe"));
+    assert!(err.downcast_ref::<SelectionError>().is_some());
+    assert!(err
+        .to_string()
+        .starts_with("No function found for package unsubscribe"));
+}
+
+#[test]
+fn multiple_matching_functions() {
+    let function_envs = FunctionEnv::load_all(&fixture("functions.yaml"), &package("subscribe"))
+        .expect("functions should load");
+    let ids: Vec<_> = function_envs.iter().map(|f| f.logical_id()).collect();
+    assert_eq!(ids, ["SubscribeHttpFunction", "SubscribeQueueFunction"]);
+}
+
+#[test]
+fn globals_are_merged_and_overridden() {
+    let function_envs = FunctionEnv::load_all(
+        &fixture("functions.yaml"),
+        &logical_id("SubscribeHttpFunction"),
+    )
+    .expect("functions should load");
+    let example = function_envs[0].env_example();
+    assert!(example.contains("APP_ID=tinykit\n"));
+    assert!(example.contains("LOG_LEVEL=debug\n"));
+    assert!(!example.contains("LOG_LEVEL=info\n"));
+    assert!(example.contains("# !Ref SubscriptionsTable\nTABLE_NAME=\n"));
+}
+
+#[test]
+fn no_environment() {
+    // matched by the end of CodeUri, ignoring the trailing slash
+    let function_envs =
+        FunctionEnv::load_all(&fixture("functions.yaml"), &package("no_environment"))
+            .expect("functions should load");
+    assert_eq!(function_envs.len(), 1);
+    let function_env = &function_envs[0];
+    assert_eq!(function_env.logical_id(), "NoEnvironmentFunction");
+    assert_eq!(function_env.code_uri(), Some("lambdas/no_environment/"));
+
+    // only the globals
+    let code = generate(function_env);
+    assert!(code.contains("pub app_id: String"));
+    assert!(code.contains("pub log_level: String"));
+}
+
+#[test]
+fn lowercase_collision() {
+    let err = load_error("lowercase_collision.yaml", &logical_id("SubscribeFunction"));
+    assert_eq!(
+        err.to_string(),
+        "Environment variables FOO_BAR and foo_bar of SubscribeFunction would both generate the field foo_bar"
+    );
+}
+
+#[test]
+fn non_string_key() {
+    let err = load_error("non_string_key.yaml", &logical_id("SubscribeFunction"));
+    assert!(err
+        .to_string()
+        .starts_with("The environment variable names of SubscribeFunction should be strings"));
+}
+
+#[test]
+fn declared_types() {
+    let function_envs = FunctionEnv::load_all(&fixture("typed.yaml"), &logical_id("TypedFunction"))
+        .expect("functions should load");
+    let code = generate(&function_envs[0]);
+    assert!(code.contains(
+        "#[envconfig(from = \"TOKEN_TTL\", default = \"24h\")]\npub token_ttl: types::Duration,"
+    ));
+    assert!(code.contains(
+        "#[envconfig(from = \"MAX_SEND_RATE\", default = \"10\")]\npub max_send_rate: u32,"
+    ));
+    assert!(code.contains(
+        "#[envconfig(from = \"ALLOWED_ORIGINS\")]\npub allowed_origins: Option<types::List<url::Url>>,"
+    ));
+    assert!(code.contains("pub dry_run: bool,"));
+    assert!(code.contains("pub name: String,"));
+    assert!(code.contains("\"DRY_RUN\" => \"true or false\","));
+    assert!(code.contains("mod types {"));
+}
+
+#[test]
+fn optional_with_default() {
+    let function_envs = FunctionEnv::load_all(&fixture("typed.yaml"), &logical_id("TypedFunction"))
+        .expect("functions should load");
+    let code = generate(&function_envs[0]);
+    // envconfig rejects a default on an Option, the constructors apply it
+    assert!(code.contains(
+        "#[envconfig(from = \"REMINDER_AFTER\")]\npub reminder_after: Option<types::Duration>,"
+    ));
+    assert!(code.contains("for (name, default) in [(\"REMINDER_AFTER\", \"1d\")]"));
+    assert_eq!(code.matches("Self::apply_defaults(&mut env);").count(), 2);
+}
+
+#[test]
+fn nested_list() {
+    let err = load_error("typed.yaml", &logical_id("NestedListFunction"));
+    assert_eq!(
+        err.to_string(),
+        "Nested lists are not supported: List<List<u64>>"
+    );
+}
+
+#[test]
+fn unsupported_type() {
+    let err = load_error("typed.yaml", &logical_id("UnsupportedTypeFunction"));
+    assert_eq!(err.to_string(), "Unsupported variable type: f64");
+}
+
+#[test]
+fn typed_secret() {
+    let err = load_error("typed.yaml", &logical_id("TypedSecretFunction"));
+    assert_eq!(
+        err.to_string(),
+        "Secret variable SIGNING_SECRET should be a String"
+    );
+}
@@ -0,0 +1,59 @@
+// the helper types are included in the generated code, compile them here
+#[path = "../src/codegen/types.rs"]
+mod types;
+
+use std::time::Duration;
+use types::List;
+
+fn duration(s: &str) -> Result<Duration, String> {
+    s.parse::<types::Duration>().map(|d| *d)
+}
+
+#[test]
+fn duration_units() {
+    assert_eq!(duration("86400"), Ok(Duration::from_secs(86400)));
+    assert_eq!(duration("500ms"), Ok(Duration::from_millis(500)));
+    assert_eq!(duration("60s"), Ok(Duration::from_secs(60)));
+    assert_eq!(duration("15m"), Ok(Duration::from_secs(15 * 60)));
+    assert_eq!(duration(" 24 h "), Ok(Duration::from_secs(24 * 60 * 60)));
+    assert_eq!(duration("7d"), Ok(Duration::from_secs(7 * 24 * 60 * 60)));
+}
+
+#[test]
+fn invalid_duration() {
+    assert_eq!(duration(""), Err("Invalid duration: ".to_string()));
+    assert_eq!(duration("-1s"), Err("Invalid duration: -1s".to_string()));
+    assert_eq!(
+        duration("1.5h"),
+        Err("Invalid duration unit: 1.5h".to_string())
+    );
+    assert_eq!(duration("2w"), Err("Invalid duration unit: 2w".to_string()));
+}
+
+#[test]
+fn duration_overflow() {
+    let max = u64::MAX.to_string();
+    assert_eq!(duration(&max), Ok(Duration::from_secs(u64::MAX)));
+    assert_eq!(
+        duration(&format!("{max}ms")),
+        Ok(Duration::from_millis(u64::MAX))
+    );
+    assert_eq!(
+        duration(&format!("{max}d")),
+        Err(format!("Duration too large: {max}d"))
+    );
+    assert_eq!(
+        duration("213503982334601d"),
+        Ok(Duration::from_secs(213503982334601 * 86400))
+    );
+    assert!(duration("213503982334602d").is_err());
+    assert!(duration(&format!("1{max}")).is_err());
+}
+
+#[test]
+fn lists() {
+    let list: List<u64> = " 1, 2,,3 ,".parse().unwrap();
+    assert_eq!(*list, [1, 2, 3]);
+    assert!("".parse::<List<String>>().unwrap().is_empty());
+    assert!("1,two".parse::<List<u64>>().is_err());
+}
@@ -0,0 +1,162 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use sam_env::{FunctionEnv, FunctionSelector, SelectionError, StructDecl};
+use std::path::PathBuf;
+use syn::{parse_macro_input, spanned::Spanned, Fields, ItemStruct, LitBool, LitStr};
+
+/// Generates the fields of a struct from the environment variables that a SAM
+/// template declares for a function.
+///
+/// ```ignore
+/// #[sam_env(template = "../../template.yaml")]
+/// struct SamEnv;
+/// ```
+///
+/// The template path is relative to the crate manifest. The function is picked
+/// by its logical ID when given with `function = "SubscribeFunction"`,
+/// otherwise by the crate (see `sam_env::FunctionSelector::Package`): when
+/// more functions are built from the crate, one struct is generated for each
+/// of them, named after the logical ID followed by the struct name
+/// (`SubscribeFunctionSamEnv`).
+///
+/// The `Policies` of the function are checked against the AWS SDK operations
+/// the crate calls, failing the build on missing permissions (see
+/// `sam_env::FunctionEnv::check_policies`). Opt out with
+/// `check_policies = false`.
+#[proc_macro_attribute]
+pub fn sam_env(args: TokenStream, input: TokenStream) -> TokenStream {
+    let mut template: Option<LitStr> = None;
+    let mut function: Option<LitStr> = None;
+    let mut check_policies = true;
+    let args_parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("template") {
+            template = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("function") {
+            function = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("check_policies") {
+            check_policies = meta.value()?.parse::<LitBool>()?.value;
+            Ok(())
+        } else {
+            Err(meta.error(
+                "unsupported sam_env argument, expected `template`, `function` or `check_policies`",
+            ))
+        }
+    });
+    parse_macro_input!(args with args_parser);
+    let item = parse_macro_input!(input as ItemStruct);
+
+    expand(template, function, check_policies, item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(
+    template: Option<LitStr>,
+    function: Option<LitStr>,
+    check_policies: bool,
+    item: ItemStruct,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let template = template.ok_or_else(|| {
+        syn::Error::new(
+            Span::call_site(),
+            "missing template path, e.g. `#[sam_env(template = \"../../template.yaml\")]`",
+        )
+    })?;
+    if !matches!(item.fields, Fields::Unit) {
+        return Err(syn::Error::new(
+            item.fields.span(),
+            "sam_env generates the fields of the struct, declare it as `struct Name;`",
+        ));
+    }
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            item.generics.span(),
+            "sam_env structs can't be generic",
+        ));
+    }
+
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+        .map_err(|_| syn::Error::new(Span::call_site(), "CARGO_MANIFEST_DIR is not set"))?;
+    let manifest_dir = PathBuf::from(manifest_dir);
+    let template_path = manifest_dir.join(template.value());
+
+    let selector = match &function {
+        Some(function) => FunctionSelector::LogicalId(function.value()),
+        None => FunctionSelector::Package {
+            package: std::env::var("CARGO_PKG_NAME")
+                .map_err(|_| syn::Error::new(Span::call_site(), "CARGO_PKG_NAME is not set"))?,
+            binary: std::env::var("CARGO_BIN_NAME").ok(),
+        },
+    };
+
+    let function_envs = FunctionEnv::load_all(&template_path, &selector).map_err(|err| {
+        let span = match (&function, err.downcast_ref::<SelectionError>()) {
+            (Some(function), Some(_)) => function.span(),
+            _ => template.span(),
+        };
+        syn::Error::new(span, format!("{:#}", err))
+    })?;
+
+    if check_policies {
+        let mut missing = vec![];
+        for function_env in &function_envs {
+            let report = function_env
+                .check_policies(&manifest_dir)
+                .map_err(|err| syn::Error::new(template.span(), format!("{:#}", err)))?;
+            if let Some(report) = report.filter(|r| !r.is_ok()) {
+                missing.push(report.to_string());
+            }
+        }
+        if !missing.is_empty() {
+            return Err(syn::Error::new(
+                template.span(),
+                format!(
+                    "missing permissions in the template (opt out with `check_policies = false`):\n{}",
+                    missing.join("\n")
+                ),
+            ));
+        }
+    }
+
+    let attributes = item
+        .attrs
+        .iter()
+        .map(|attr| quote!(#attr).to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let visibility = &item.vis;
+    let visibility = quote!(#visibility).to_string();
+
+    let mut generated = proc_macro2::TokenStream::new();
+    for function_env in &function_envs {
+        // with more functions built from the same crate, every struct is
+        // prefixed with the logical ID of its function
+        let name = match function_envs.len() {
+            1 => item.ident.to_string(),
+            _ => format!("{}{}", function_env.logical_id(), item.ident),
+        };
+        let code = function_env.generate_struct(&StructDecl {
+            attributes: &attributes,
+            visibility: &visibility,
+            name: &name,
+        });
+        let tokens: proc_macro2::TokenStream = code.parse().map_err(|err| {
+            syn::Error::new(
+                template.span(),
+                format!("sam_env generated invalid code: {}", err),
+            )
+        })?;
+        generated.extend(tokens);
+    }
+
+    let template_path = template_path.to_string_lossy().to_string();
+    Ok(quote! {
+        #generated
+
+        // rebuild when the template changes
+        const _: &[u8] = include_bytes!(#template_path);
+    })
+}
@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+use crate::campaign::ValidationErrors;
+
+/// A newsletter sent to the confirmed subscribers of a campaign.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Broadcast {
+    pub campaign_id: String,
+    pub broadcast_id: String,
+    pub subject: String,
+    /// The key of the HTML template in the resources bucket
+    pub template_s3_key: String,
+    pub status: BroadcastStatus,
+    /// How many subscribers it has been queued for so far
+    #[serde(default)]
+    pub recipients: u64,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u64>,
+    /// When the last subscriber was queued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BroadcastStatus {
+    Draft,
+    Sending,
+    Sent,
+}
+
+impl Broadcast {
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = vec![];
+        if self.subject.trim().is_empty() {
+            errors.push("subject can't be empty".to_string());
+        }
+        if self.template_s3_key.is_empty()
+            || self.template_s3_key.starts_with('/')
+            || self
+                .template_s3_key
+                .split('/')
+                .any(|segment| segment == "..")
+        {
+            errors.push("template_s3_key should be a relative S3 key".to_string());
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(ValidationErrors(errors)),
+        }
+    }
+}
+
+/// The delivery of a broadcast to a subscriber.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastDelivery {
+    pub broadcast_id: String,
+    pub subscription_id: String,
+    pub campaign_id: String,
+    pub email: String,
+    pub status: DeliveryStatus,
+    pub queued_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ses_message_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Queued,
+    Sent,
+    Failed,
+    /// The subscriber unsubscribed (or was suppressed) before it was sent
+    Skipped,
+}
+
+/// A message in the broadcast queue: queue the broadcast for a page of the
+/// subscribers of the campaign, starting after `start_after`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FanOutPage {
+    pub campaign_id: String,
+    pub broadcast_id: String,
+    #[serde(default)]
+    pub start_after: Option<String>,
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::email::normalize_email;
+use crate::fields::{validate_fields, FormField};
+use crate::i18n::{is_valid_locale, Text};
+
+/// A campaign, as stored in the campaigns table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Campaign {
+    pub campaign_id: String,
+    pub name: String,
+    /// The key of the reward file in the resources bucket
+    #[serde(default)]
+    pub reward_s3_key: String,
+    /// The key of the confirmation email template in the resources bucket
+    #[serde(default)]
+    pub email_template_s3_key: String,
+    pub thank_you_message: String,
+    /// The address the emails are sent from, a verified SES identity (or of
+    /// a verified domain), instead of the sender of the deployment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender_email: Option<String>,
+    /// The name the emails are sent from, next to the sender address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_name: Option<String>,
+    /// Where the replies to the emails go, instead of the sender address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    /// The locale of the texts of the campaign (`en` when missing)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// The texts shown to the subscribers, by locale, overriding the
+    /// English ones
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub translations: BTreeMap<String, BTreeMap<Text, String>>,
+    /// The extra fields of the subscription form, after the email
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FormField>,
+    /// The follow-up emails sent after the confirmation, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drip: Vec<DripStep>,
+    // campaigns created before the status was introduced are active
+    #[serde(default)]
+    pub status: CampaignStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CampaignStatus {
+    #[default]
+    Active,
+    Archived,
+}
+
+/// A follow-up email of the drip sequence of a campaign.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DripStep {
+    /// How long after the confirmation it is sent (`1d`, `72h`, ...)
+    pub after: String,
+    pub subject: String,
+    /// The key of the HTML template in the resources bucket
+    pub template_s3_key: String,
+}
+
+impl DripStep {
+    pub fn delay(&self) -> Option<Duration> {
+        humantime::parse_duration(&self.after).ok()
+    }
+}
+
+/// The reasons why a campaign isn't valid.
+#[derive(Debug)]
+pub struct ValidationErrors(pub Vec<String>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join(", "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+const MAX_CAMPAIGN_ID_LENGTH: usize = 64;
+const MAX_NAME_LENGTH: usize = 256;
+const MAX_FROM_NAME_LENGTH: usize = 64;
+
+impl Campaign {
+    pub fn is_active(&self) -> bool {
+        self.status == CampaignStatus::Active
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = vec![];
+
+        // the campaign ID ends up in the form URL and in the S3 keys
+        if self.campaign_id.is_empty() || self.campaign_id.len() > MAX_CAMPAIGN_ID_LENGTH {
+            errors.push(format!(
+                "campaign_id should be between 1 and {} characters",
+                MAX_CAMPAIGN_ID_LENGTH
+            ));
+        }
+        if !self
+            .campaign_id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        {
+            errors.push(
+                "campaign_id can only contain lowercase letters, digits, '-' and '_'".to_string(),
+            );
+        }
+
+        if self.name.trim().is_empty() || self.name.len() > MAX_NAME_LENGTH {
+            errors.push(format!(
+                "name should be between 1 and {} characters",
+                MAX_NAME_LENGTH
+            ));
+        }
+        if self.thank_you_message.trim().is_empty() {
+            errors.push("thank_you_message can't be empty".to_string());
+        }
+
+        if let Some(from_name) = &self.from_name {
+            if from_name.trim().is_empty()
+                || from_name.chars().count() > MAX_FROM_NAME_LENGTH
+                || from_name.chars().any(char::is_control)
+            {
+                errors.push(format!(
+                    "from_name should be between 1 and {} characters, on one line",
+                    MAX_FROM_NAME_LENGTH
+                ));
+            }
+        }
+        for locale in self.locale.iter().chain(self.translations.keys()) {
+            if !is_valid_locale(locale) {
+                errors.push(format!(
+                    "{:?} is not a valid locale, expected e.g. en or pt-BR",
+                    locale
+                ));
+            }
+        }
+
+        if let Some(sender_email) = &self.sender_email {
+            if normalize_email(sender_email).is_none() {
+                errors.push("sender_email should be an email address".to_string());
+            }
+        }
+        if let Some(reply_to) = &self.reply_to {
+            if normalize_email(reply_to).is_none() {
+                errors.push("reply_to should be an email address".to_string());
+            }
+        }
+
+        for (field, key) in [
+            ("reward_s3_key", &self.reward_s3_key),
+            ("email_template_s3_key", &self.email_template_s3_key),
+        ] {
+            if key.starts_with('/') || key.split('/').any(|segment| segment == "..") {
+                errors.push(format!("{} should be a relative S3 key", field));
+            }
+        }
+
+        validate_fields(&self.fields, &mut errors);
+
+        let mut previous_delay = Duration::ZERO;
+        for (i, step) in self.drip.iter().enumerate() {
+            match step.delay() {
+                Some(delay) if delay < previous_delay => errors.push(format!(
+                    "drip step {} should come after the previous one",
+                    i
+                )),
+                Some(delay) => previous_delay = delay,
+                None => errors.push(format!(
+                    "drip step {} has an invalid delay {:?}, expected e.g. 1d or 72h",
+                    i, step.after
+                )),
+            }
+            if step.subject.trim().is_empty() {
+                errors.push(format!("drip step {} has an empty subject", i));
+            }
+            if step.template_s3_key.is_empty()
+                || step.template_s3_key.starts_with('/')
+                || step
+                    .template_s3_key
+                    .split('/')
+                    .any(|segment| segment == "..")
+            {
+                errors.push(format!(
+                    "drip step {} should have a relative template_s3_key",
+                    i
+                ));
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(ValidationErrors(errors)),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use validators::models::Host;
+use validators::prelude::*;
+
+#[derive(Validator)]
+#[validator(email(
+    comment(Disallow),
+    ip(Allow),
+    local(Allow),
+    at_least_two_labels(Allow),
+    non_ascii(Allow)
+))]
+pub struct Email {
+    pub local_part: String,
+    pub need_quoted: bool,
+    pub domain_part: Host,
+}
+
+/// Validates an address with the rules of the subscription form, returning
+/// it trimmed and with a lowercase domain, so the same address is always
+/// stored the same way.
+pub fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim();
+    Email::parse_str(email).ok()?;
+    let (local_part, domain) = email.rsplit_once('@')?;
+    Some(format!("{}@{}", local_part, domain.to_lowercase()))
+}
+
+/// The most bytes of text an encoded-word can hold: RFC 2047 limits them to
+/// 75 characters, 12 of which are taken by `=?UTF-8?B?` and `?=`, leaving 60
+/// characters of base64.
+const MAX_ENCODED_WORD_BYTES: usize = 45;
+
+/// Formats an address with a display name (`"Name" <address>`), encoding
+/// the name as RFC 2047 when it's not plain ASCII.
+pub fn format_address(name: Option<&str>, email: &str) -> String {
+    match name {
+        None => email.to_string(),
+        Some(name) if name.is_ascii() => format!(
+            "\"{}\" <{}>",
+            name.replace('\\', "\\\\").replace('"', "\\\""),
+            email
+        ),
+        Some(name) => format!("{} <{}>", encode_words(name), email),
+    }
+}
+
+/// Encodes a text as RFC 2047 encoded-words, split between characters when
+/// it doesn't fit in one.
+fn encode_words(text: &str) -> String {
+    let mut words = vec![];
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if i + c.len_utf8() - start > MAX_ENCODED_WORD_BYTES {
+            words.push(&text[start..i]);
+            start = i;
+        }
+    }
+    words.push(&text[start..]);
+    words
+        .into_iter()
+        .map(|word| format!("=?UTF-8?B?{}?=", STANDARD.encode(word)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
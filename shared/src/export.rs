@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use serde::{Deserialize, Serialize};
+
+use crate::subscription::{Subscription, SubscriptionStatus};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+}
+
+/// Which subscriptions to export. The date range applies to the creation
+/// date of the subscriptions (unix timestamps, `to` excluded), so the ones
+/// created before it was recorded only match when no range is given.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportFilter {
+    #[serde(default)]
+    pub status: Option<SubscriptionStatus>,
+    #[serde(default)]
+    pub from: Option<u64>,
+    #[serde(default)]
+    pub to: Option<u64>,
+}
+
+impl ExportFilter {
+    pub fn matches(&self, subscription: &Subscription) -> bool {
+        if let Some(status) = self.status {
+            if subscription.status() != status {
+                return false;
+            }
+        }
+        match (self.from, self.to, subscription.created_at) {
+            (None, None, _) => true,
+            (_, _, None) => false,
+            (from, to, Some(created_at)) => {
+                from.map(|from| created_at >= from).unwrap_or(true)
+                    && to.map(|to| created_at < to).unwrap_or(true)
+            }
+        }
+    }
+
+    /// The filter expression selecting the same subscriptions in a query,
+    /// with its values, so that DynamoDB only returns the matching ones.
+    pub fn filter_expression(&self) -> Option<(String, Vec<(&'static str, AttributeValue)>)> {
+        let mut conditions = vec![];
+        let mut values = vec![];
+        match self.status {
+            Some(SubscriptionStatus::Pending) => conditions.push(
+                "attribute_not_exists(confirmed_at) AND attribute_not_exists(unsubscribed_at)",
+            ),
+            Some(SubscriptionStatus::Confirmed) => conditions
+                .push("attribute_exists(confirmed_at) AND attribute_not_exists(unsubscribed_at)"),
+            Some(SubscriptionStatus::Unsubscribed) => {
+                conditions.push("attribute_exists(unsubscribed_at)")
+            }
+            None => {}
+        }
+        // subscriptions without created_at don't match the comparisons
+        if let Some(from) = self.from {
+            conditions.push("created_at >= :from");
+            values.push((":from", AttributeValue::N(from.to_string())));
+        }
+        if let Some(to) = self.to {
+            conditions.push("created_at < :to");
+            values.push((":to", AttributeValue::N(to.to_string())));
+        }
+        (!conditions.is_empty()).then(|| (conditions.join(" AND "), values))
+    }
+
+    /// The S3 key of an export of the campaign started at `now`.
+    pub fn s3_key(&self, campaign_id: &str, format: ExportFormat, now: u64) -> String {
+        let status = match self.status {
+            Some(SubscriptionStatus::Pending) => "pending",
+            Some(SubscriptionStatus::Confirmed) => "confirmed",
+            Some(SubscriptionStatus::Unsubscribed) => "unsubscribed",
+            None => "all",
+        };
+        format!(
+            "exports/{}/{}-{}.{}",
+            campaign_id,
+            now,
+            status,
+            format.extension()
+        )
+    }
+}
+
+/// A subscription as exported, with its status and readable timestamps.
+#[derive(Debug, Serialize)]
+struct ExportRecord {
+    subscription_id: String,
+    campaign_id: String,
+    email: String,
+    status: SubscriptionStatus,
+    ip: String,
+    created_at: String,
+    sent_at: String,
+    send_attempts: u32,
+    ses_message_id: String,
+    opened_at: String,
+    confirmed_at: String,
+    unsubscribed_at: String,
+}
+
+impl From<Subscription> for ExportRecord {
+    fn from(subscription: Subscription) -> Self {
+        let timestamp = |t: Option<u64>| t.map(format_timestamp).unwrap_or_default();
+        Self {
+            status: subscription.status(),
+            created_at: timestamp(subscription.created_at),
+            sent_at: timestamp(subscription.sent_at),
+            send_attempts: subscription.send_attempts.unwrap_or_default(),
+            ses_message_id: subscription.ses_message_id.clone().unwrap_or_default(),
+            opened_at: timestamp(subscription.opened_at),
+            confirmed_at: timestamp(subscription.confirmed_at),
+            unsubscribed_at: timestamp(subscription.unsubscribed_at),
+            subscription_id: subscription.subscription_id,
+            campaign_id: subscription.campaign_id,
+            email: subscription.email,
+            ip: subscription.ip,
+        }
+    }
+}
+
+/// Formats a unix timestamp as RFC 3339 (`2024-06-01T12:00:00Z`).
+pub fn format_timestamp(timestamp: u64) -> String {
+    humantime::format_rfc3339_seconds(SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp))
+        .to_string()
+}
+
+/// Parses an RFC 3339 date (`2024-06-01`) or date and time
+/// (`2024-06-01T12:00:00Z`) into a unix timestamp.
+pub fn parse_timestamp(value: &str) -> Result<u64> {
+    let value = match value.len() {
+        10 => format!("{}T00:00:00Z", value),
+        _ => value.to_string(),
+    };
+    let time = humantime::parse_rfc3339_weak(&value)
+        .map_err(|err| anyhow!("Invalid date {}: {}", value, err))?;
+    Ok(time.duration_since(SystemTime::UNIX_EPOCH)?.as_secs())
+}
+
+/// Writes the exported subscriptions in a format, a page at a time.
+pub struct ExportWriter<W: Write> {
+    format: ExportFormat,
+    output: W,
+    /// Whether the CSV header was written with a previous page.
+    has_header: bool,
+}
+
+impl<W: Write> ExportWriter<W> {
+    pub fn new(format: ExportFormat, output: W) -> Self {
+        ExportWriter {
+            format,
+            output,
+            has_header: false,
+        }
+    }
+
+    /// Writes a page of subscriptions, returning how many they were.
+    pub fn write(
+        &mut self,
+        subscriptions: impl IntoIterator<Item = Subscription>,
+    ) -> Result<usize> {
+        let mut count = 0;
+        match self.format {
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(!self.has_header)
+                    .from_writer(&mut self.output);
+                for subscription in subscriptions {
+                    writer.serialize(ExportRecord::from(subscription))?;
+                    count += 1;
+                }
+                writer.flush()?;
+                self.has_header |= count > 0;
+            }
+            ExportFormat::Jsonl => {
+                for subscription in subscriptions {
+                    let record = ExportRecord::from(subscription);
+                    writeln!(self.output, "{}", serde_json::to_string(&record)?)?;
+                    count += 1;
+                }
+                self.output.flush()?;
+            }
+        }
+        Ok(count)
+    }
+
+    /// The output, with everything written so far.
+    pub fn output(&mut self) -> &mut W {
+        &mut self.output
+    }
+}
+
+/// The query of the subscriptions of a campaign matching the filter, to be
+/// paged through rather than loaded all at once.
+fn export_query(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    campaign_id: &str,
+    filter: &ExportFilter,
+) -> aws_sdk_dynamodb::operation::query::builders::QueryFluentBuilder {
+    let mut request = client
+        .query()
+        .table_name(table_name)
+        .key_condition_expression("campaign_id = :campaign_id")
+        .expression_attribute_values(":campaign_id", AttributeValue::S(campaign_id.to_string()));
+    if let Some((expression, values)) = filter.filter_expression() {
+        request = request.filter_expression(expression);
+        for (name, value) in values {
+            request = request.expression_attribute_values(name, value);
+        }
+    }
+    request
+}
+
+/// Writes the subscriptions of a page of the query, returning how many.
+fn write_page<W: Write>(
+    items: Option<Vec<HashMap<String, AttributeValue>>>,
+    filter: &ExportFilter,
+    writer: &mut ExportWriter<W>,
+) -> Result<usize> {
+    let subscriptions: Vec<Subscription> = serde_dynamo::from_items(items.unwrap_or_default())?;
+    // the filter expression can't tell an attribute stored as NULL from one
+    // holding a date
+    writer.write(subscriptions.into_iter().filter(|s| filter.matches(s)))
+}
+
+/// Exports the subscriptions of a campaign matching the filter to `output`
+/// as the pages of the query arrive, returning how many they were.
+pub async fn export_to_writer(
+    client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    campaign_id: &str,
+    filter: &ExportFilter,
+    format: ExportFormat,
+    output: impl Write,
+) -> Result<usize> {
+    let mut writer = ExportWriter::new(format, output);
+    let mut pages = export_query(client, table_name, campaign_id, filter)
+        .into_paginator()
+        .send();
+    let mut count = 0;
+    while let Some(page) = pages.next().await {
+        count += write_page(page?.items, filter, &mut writer)?;
+    }
+    Ok(count)
+}
+
+/// The smallest size of the parts of a multipart upload, but the last one.
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Exports the subscriptions of a campaign matching the filter to S3,
+/// returning how many they were. The file is uploaded in parts as the pages
+/// of the query arrive, or in one go when it's smaller than a part. An
+/// upload left incomplete by an error is cleaned up by the bucket lifecycle.
+#[allow(clippy::too_many_arguments)]
+pub async fn export_to_s3(
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    campaign_id: &str,
+    filter: &ExportFilter,
+    format: ExportFormat,
+    s3_client: &aws_sdk_s3::Client,
+    bucket: &str,
+    s3_key: &str,
+) -> Result<usize> {
+    let mut writer = ExportWriter::new(format, vec![]);
+    let mut pages = export_query(dynamodb_client, table_name, campaign_id, filter)
+        .into_paginator()
+        .send();
+    let mut upload_id = None;
+    let mut parts = vec![];
+    let mut count = 0;
+    while let Some(page) = pages.next().await {
+        count += write_page(page?.items, filter, &mut writer)?;
+        let buffer = writer.output();
+        if buffer.len() < MIN_PART_SIZE {
+            continue;
+        }
+        let content = std::mem::take(buffer);
+        let upload_id = match &upload_id {
+            Some(upload_id) => upload_id,
+            None => upload_id.insert(
+                s3_client
+                    .create_multipart_upload()
+                    .bucket(bucket)
+                    .key(s3_key)
+                    .content_type(format.content_type())
+                    .send()
+                    .await?
+                    .upload_id
+                    .ok_or_else(|| anyhow!("No upload id for {}", s3_key))?,
+            ),
+        };
+        let part_number = parts.len() as i32 + 1;
+        parts.push(upload_part(s3_client, bucket, s3_key, upload_id, part_number, content).await?);
+    }
+    let content = std::mem::take(writer.output());
+
+    match upload_id {
+        None => {
+            s3_client
+                .put_object()
+                .bucket(bucket)
+                .key(s3_key)
+                .content_type(format.content_type())
+                .body(ByteStream::from(content))
+                .send()
+                .await?;
+        }
+        Some(upload_id) => {
+            if !content.is_empty() {
+                let part_number = parts.len() as i32 + 1;
+                parts.push(
+                    upload_part(s3_client, bucket, s3_key, &upload_id, part_number, content)
+                        .await?,
+                );
+            }
+            s3_client
+                .complete_multipart_upload()
+                .bucket(bucket)
+                .key(s3_key)
+                .upload_id(upload_id)
+                .multipart_upload(
+                    CompletedMultipartUpload::builder()
+                        .set_parts(Some(parts))
+                        .build(),
+                )
+                .send()
+                .await?;
+        }
+    }
+    Ok(count)
+}
+
+async fn upload_part(
+    s3_client: &aws_sdk_s3::Client,
+    bucket: &str,
+    s3_key: &str,
+    upload_id: &str,
+    part_number: i32,
+    content: Vec<u8>,
+) -> Result<CompletedPart> {
+    let output = s3_client
+        .upload_part()
+        .bucket(bucket)
+        .key(s3_key)
+        .upload_id(upload_id)
+        .part_number(part_number)
+        .body(ByteStream::from(content))
+        .send()
+        .await?;
+    Ok(CompletedPart::builder()
+        .part_number(part_number)
+        .set_e_tag(output.e_tag)
+        .build())
+}
@@ -0,0 +1,153 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// An extra field of the subscription form of a campaign.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormField {
+    /// The name of the input, and of the value on the subscription
+    pub name: String,
+    #[serde(default)]
+    pub label: String,
+    #[serde(rename = "type")]
+    pub kind: FieldKind,
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldKind {
+    Text,
+    Number,
+    /// A yes/no value, e.g. a consent (required means it has to be checked)
+    Checkbox,
+    /// Not shown, filled from the query parameter of the form URL with the
+    /// same name, or else from the request header (e.g. `utm_source` or
+    /// `referer`)
+    Hidden,
+}
+
+/// The value of a field, stored with its type on the subscription.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::Bool(value) => write!(f, "{}", value),
+            FieldValue::Number(value) => write!(f, "{}", value),
+            FieldValue::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+const MAX_FIELD_NAME_LENGTH: usize = 64;
+const MAX_VALUE_LENGTH: usize = 256;
+
+/// The names of the inputs of the form itself.
+const RESERVED_NAMES: [&str; 2] = ["email", "locale"];
+
+/// Checks the fields of a campaign, adding the problems to `errors`.
+pub fn validate_fields(fields: &[FormField], errors: &mut Vec<String>) {
+    for (i, field) in fields.iter().enumerate() {
+        if field.name.is_empty()
+            || field.name.len() > MAX_FIELD_NAME_LENGTH
+            || !field
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        {
+            errors.push(format!(
+                "field {} should have a name of 1 to {} lowercase letters, digits and '_'",
+                i, MAX_FIELD_NAME_LENGTH
+            ));
+        }
+        if RESERVED_NAMES.contains(&field.name.as_str()) {
+            errors.push(format!("field {} can't be named {}", i, field.name));
+        }
+        if fields[..i].iter().any(|other| other.name == field.name) {
+            errors.push(format!("field {} is a duplicate of {}", i, field.name));
+        }
+        if field.kind != FieldKind::Hidden && field.label.trim().is_empty() {
+            errors.push(format!("field {} should have a label", i));
+        }
+        // the visitor couldn't fill it in when the form URL lacks it
+        if field.kind == FieldKind::Hidden && field.required {
+            errors.push(format!("field {} can't be both hidden and required", i));
+        }
+    }
+}
+
+/// Reads the values of the fields from a form submission, returning the
+/// labels (or names) of the invalid ones otherwise. Unknown values are
+/// ignored, and so are the empty optional ones.
+pub fn parse_fields(
+    fields: &[FormField],
+    values: &HashMap<String, String>,
+) -> Result<BTreeMap<String, FieldValue>, Vec<String>> {
+    let mut parsed = BTreeMap::new();
+    let mut invalid = vec![];
+    for field in fields {
+        let value = values
+            .get(&field.name)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty());
+        let value = match (field.kind, value) {
+            // browsers only send the checked boxes
+            (FieldKind::Checkbox, value) => Ok(Some(FieldValue::Bool(value.is_some()))),
+            (_, None) => Ok(None),
+            (FieldKind::Number, Some(value)) => value
+                .parse()
+                .ok()
+                .filter(|value: &f64| value.is_finite())
+                .map(|value| Some(FieldValue::Number(value)))
+                .ok_or(()),
+            (_, Some(value)) if value.chars().count() > MAX_VALUE_LENGTH => Err(()),
+            (_, Some(value)) => Ok(Some(FieldValue::Text(value.to_string()))),
+        };
+        match value {
+            Ok(None | Some(FieldValue::Bool(false))) if field.required => {}
+            Ok(Some(value)) => {
+                parsed.insert(field.name.clone(), value);
+                continue;
+            }
+            Ok(None) => continue,
+            Err(()) => {}
+        }
+        invalid.push(match field.label.is_empty() {
+            true => field.name.clone(),
+            false => field.label.clone(),
+        });
+    }
+    match invalid.is_empty() {
+        true => Ok(parsed),
+        false => Err(invalid),
+    }
+}
+
+/// The values of the fields of a subscription, as template variables
+/// (`{{ fields.first_name }}`), empty for the missing ones.
+pub fn field_variables(
+    fields: &[FormField],
+    values: &BTreeMap<String, FieldValue>,
+) -> Vec<(String, String)> {
+    fields
+        .iter()
+        .map(|field| {
+            (
+                format!("fields.{}", field.name),
+                values
+                    .get(&field.name)
+                    .map(FieldValue::to_string)
+                    .unwrap_or_default(),
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+
+use crate::campaign::Campaign;
+
+/// The locale of the campaigns that don't declare one.
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// The texts shown to the subscribers, which a campaign can translate. The
+/// ones with placeholders are rendered with [`crate::render::render`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Text {
+    FormTitle,
+    FormEmailLabel,
+    FormSubmit,
+    InvalidEmail,
+    /// A field of the form with an invalid value, with `{{ field }}`
+    InvalidField,
+    CampaignNotFound,
+    /// The page after subscribing, with `{{ email }}`
+    Subscribed,
+    ConfirmationSubject,
+    ReminderSubject,
+    /// The plain text body of the confirmation email, with
+    /// `{{ confirmation_url }}`
+    ConfirmationText,
+    /// The HTML body of the confirmation email, with `{{ confirmation_url }}`
+    ConfirmationHtml,
+    InvalidToken,
+    InvalidSubscription,
+    InvalidUnsubscribeLink,
+    Unsubscribed,
+    /// The `thank_you_message` of the campaign, in another locale
+    ThankYouMessage,
+}
+
+impl Text {
+    /// The English text, used when the campaign has no translation.
+    pub fn default_text(self) -> &'static str {
+        match self {
+            Text::FormTitle => "Subscribe to our newsletter",
+            Text::FormEmailLabel => "Email:",
+            Text::FormSubmit => "Submit",
+            Text::InvalidEmail => "Invalid email",
+            Text::InvalidField => "Invalid {{ field }}",
+            Text::CampaignNotFound => "Campaign not found",
+            Text::Subscribed => "Thanks! Check {{ email }} to confirm your subscription.",
+            Text::ConfirmationSubject => "Please confirm your subscription",
+            Text::ReminderSubject => "Reminder: please confirm your subscription",
+            Text::ConfirmationText => {
+                "Click here to confirm your subscription: {{ confirmation_url }}"
+            }
+            Text::ConfirmationHtml => {
+                "Click <a href=\"{{ confirmation_url }}\">here</a> to confirm your subscription"
+            }
+            Text::InvalidToken => "Invalid token",
+            Text::InvalidSubscription => "Invalid subscription",
+            Text::InvalidUnsubscribeLink => "Invalid unsubscribe link",
+            Text::Unsubscribed => "You have been unsubscribed.",
+            Text::ThankYouMessage => "",
+        }
+    }
+}
+
+/// Whether a locale looks like a BCP 47 language tag (`en`, `pt-BR`, ...).
+pub fn is_valid_locale(locale: &str) -> bool {
+    let mut subtags = locale.split('-');
+    let language = subtags.next().unwrap_or_default();
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+impl Campaign {
+    pub fn default_locale(&self) -> &str {
+        self.locale.as_deref().unwrap_or(DEFAULT_LOCALE)
+    }
+
+    /// The locales the campaign has texts for, the default one first.
+    pub fn locales(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.default_locale()).chain(
+            self.translations
+                .keys()
+                .map(String::as_str)
+                .filter(|locale| *locale != self.default_locale()),
+        )
+    }
+
+    /// A text in a locale of the campaign, falling back to its default
+    /// locale, then to its own `thank_you_message` or the English text.
+    pub fn text(&self, locale: &str, text: Text) -> &str {
+        [locale, self.default_locale()]
+            .into_iter()
+            .find_map(|locale| {
+                self.translations
+                    .get(locale)
+                    .and_then(|texts| texts.get(&text))
+            })
+            .map(String::as_str)
+            .unwrap_or(match text {
+                Text::ThankYouMessage => &self.thank_you_message,
+                text => text.default_text(),
+            })
+    }
+
+    /// The locale of the campaign to use for a visitor: the one they asked
+    /// for when the campaign has it, or the best match of their
+    /// `Accept-Language` header, or the default locale of the campaign.
+    pub fn negotiate_locale(
+        &self,
+        requested: Option<&str>,
+        accept_language: Option<&str>,
+    ) -> String {
+        requested
+            .and_then(|requested| self.find_locale(requested))
+            .or_else(|| {
+                parse_accept_language(accept_language.unwrap_or_default())
+                    .into_iter()
+                    .find_map(|range| self.find_locale(&range))
+            })
+            .unwrap_or(self.default_locale())
+            .to_string()
+    }
+
+    /// The locale matching a language range, either exactly or by its
+    /// language (`fr-CH` matches `fr`, then `pt` or `pt-PT` match `pt-BR`),
+    /// ignoring the case.
+    fn find_locale(&self, range: &str) -> Option<&str> {
+        fn language(tag: &str) -> &str {
+            tag.split('-').next().unwrap_or_default()
+        }
+        self.locales()
+            .find(|locale| locale.eq_ignore_ascii_case(range))
+            .or_else(|| {
+                self.locales()
+                    .find(|locale| locale.eq_ignore_ascii_case(language(range)))
+            })
+            .or_else(|| {
+                self.locales()
+                    .find(|locale| language(locale).eq_ignore_ascii_case(language(range)))
+            })
+    }
+}
+
+/// The language ranges of an `Accept-Language` header, by preference.
+fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut ranges: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            (!range.is_empty() && range != "*" && quality > 0.0)
+                .then(|| (range.to_string(), quality))
+        })
+        .collect();
+    // stable, so the order of the header breaks the ties
+    ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    ranges.into_iter().map(|(range, _)| range).collect()
+}
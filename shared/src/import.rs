@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+/// How the imported subscribers are confirmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// They already confirmed with the previous provider, so they're stored
+    /// as confirmed, with the provenance of the import
+    PreConfirmed,
+    /// They're sent a confirmation email, like the subscribers of the form
+    Confirm,
+}
+
+/// The event the import function is invoked with: a CSV file in the
+/// resources bucket with an `email` column (or Mailchimp's `Email Address`)
+/// and optionally a `confirmed_at` one (or `CONFIRM_TIME`/`OPTIN_TIME`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportJob {
+    pub campaign_id: String,
+    pub s3_key: String,
+    pub mode: ImportMode,
+    /// Where the subscribers come from (e.g. `mailchimp`)
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+/// Where the CSV files to import into a campaign are uploaded.
+pub fn import_prefix(campaign_id: &str) -> String {
+    format!("imports/{}/", campaign_id)
+}
+
+impl ImportJob {
+    /// Whether the file is one uploaded for the campaign, directly under its
+    /// import prefix.
+    pub fn has_valid_s3_key(&self) -> bool {
+        self.s3_key
+            .strip_prefix(&import_prefix(&self.campaign_id))
+            .map(|file_name| {
+                !file_name.is_empty()
+                    && !file_name.contains('/')
+                    && file_name != "."
+                    && file_name != ".."
+            })
+            .unwrap_or(false)
+    }
+
+    /// Where the report of the import is written, next to the CSV file.
+    pub fn report_s3_key(&self) -> String {
+        format!("{}.report.json", self.s3_key)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Addresses already subscribed to the campaign
+    pub already_subscribed: usize,
+    /// Addresses repeated in the file
+    pub duplicates: usize,
+    /// Addresses that bounced or complained, which aren't imported
+    pub suppressed: usize,
+    /// Confirmation emails queued (in `confirm` mode)
+    pub queued: usize,
+    pub invalid: Vec<InvalidRow>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvalidRow {
+    pub line: u64,
+    pub value: String,
+    pub reason: String,
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// The JSON document holding the keyring, usually stored as a secret.
+///
+/// ```json
+/// {
+///   "active_kid": "2024-06",
+///   "keys": [
+///     { "kid": "2024-06", "alg": "HS256", "secret": "..." },
+///     { "kid": "2024-01", "alg": "EdDSA", "private_key": "<base64 PKCS#8 DER>", "public_key": "<base64url x>" }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Deserialize)]
+struct KeyringDocument {
+    active_kid: String,
+    keys: Vec<KeyDocument>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyDocument {
+    kid: String,
+    #[serde(flatten)]
+    material: KeyMaterial,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "alg")]
+enum KeyMaterial {
+    HS256 {
+        secret: String,
+    },
+    EdDSA {
+        private_key: Option<String>,
+        public_key: String,
+    },
+}
+
+struct SigningKey {
+    algorithm: Algorithm,
+    encoding_key: Option<EncodingKey>,
+    decoding_key: DecodingKey,
+}
+
+/// A set of keys identified by `kid`, used to sign and verify tokens.
+///
+/// New tokens are always signed with the active key, while any key in the
+/// keyring can verify a token, so older keys can be kept around until the
+/// tokens they signed have expired.
+pub struct Keyring {
+    active_kid: String,
+    keys: HashMap<String, SigningKey>,
+}
+
+impl Keyring {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let document: KeyringDocument = serde_json::from_str(json)
+            .map_err(|err| anyhow!("Failed to parse keyring document: {}", err))?;
+
+        let mut keys = HashMap::new();
+        for key in document.keys {
+            let signing_key = match key.material {
+                KeyMaterial::HS256 { secret } => SigningKey {
+                    algorithm: Algorithm::HS256,
+                    encoding_key: Some(EncodingKey::from_secret(secret.as_ref())),
+                    decoding_key: DecodingKey::from_secret(secret.as_ref()),
+                },
+                KeyMaterial::EdDSA {
+                    private_key,
+                    public_key,
+                } => SigningKey {
+                    algorithm: Algorithm::EdDSA,
+                    encoding_key: private_key
+                        .map(|private_key| STANDARD.decode(private_key))
+                        .transpose()
+                        .map_err(|err| anyhow!("Invalid private key for kid {}: {}", key.kid, err))?
+                        .map(|der| EncodingKey::from_ed_der(&der)),
+                    decoding_key: DecodingKey::from_ed_components(&public_key).map_err(|err| {
+                        anyhow!("Invalid public key for kid {}: {}", key.kid, err)
+                    })?,
+                },
+            };
+            if keys.insert(key.kid.clone(), signing_key).is_some() {
+                return Err(anyhow!("Duplicate kid in keyring: {}", key.kid));
+            }
+        }
+
+        match keys.get(&document.active_kid) {
+            None => {
+                return Err(anyhow!(
+                    "Active kid {} not found in keyring",
+                    document.active_kid
+                ))
+            }
+            Some(key) if key.encoding_key.is_none() => {
+                return Err(anyhow!(
+                    "Active kid {} has no private key",
+                    document.active_kid
+                ))
+            }
+            Some(_) => {}
+        }
+
+        Ok(Self {
+            active_kid: document.active_kid,
+            keys,
+        })
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String> {
+        let key = &self.keys[&self.active_kid];
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(self.active_kid.clone());
+        let encoding_key = key
+            .encoding_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("Active kid {} has no private key", self.active_kid))?;
+
+        Ok(encode(&header, claims, encoding_key)?)
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<T> {
+        let header = decode_header(token)?;
+        let candidates: Vec<&SigningKey> = match &header.kid {
+            Some(kid) => vec![self
+                .keys
+                .get(kid)
+                .ok_or_else(|| anyhow!("Unknown kid: {}", kid))?],
+            // tokens signed before the keyring existed carry no kid:
+            // accept them if any key with the same algorithm can verify them
+            None => self
+                .keys
+                .values()
+                .filter(|key| key.algorithm == header.alg)
+                .collect(),
+        };
+
+        let mut last_error = anyhow!("No key available for algorithm {:?}", header.alg);
+        for key in candidates {
+            match decode::<T>(token, &key.decoding_key, &Validation::new(key.algorithm)) {
+                Ok(token_data) => return Ok(token_data.claims),
+                Err(err) => last_error = err.into(),
+            }
+        }
+        Err(last_error)
+    }
+}
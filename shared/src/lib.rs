@@ -1,5 +1,19 @@
+pub mod broadcast;
+pub mod campaign;
+pub mod email;
+pub mod export;
+pub mod fields;
+pub mod i18n;
+pub mod import;
+pub mod keyring;
+pub mod render;
+pub mod sqs;
+pub mod subscription;
+pub mod suppression;
+
 use std::time::SystemTime;
 
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -7,6 +21,54 @@ pub struct SubscribeEventPayload {
     pub subscription_id: String,
     pub campaign_id: String,
     pub email: String,
+    /// Send it even if the subscription is confirmed already
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub force: bool,
+    /// The locale of the campaign the subscriber chose
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+}
+
+/// A broadcast to send to one subscriber.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastEmail {
+    pub campaign_id: String,
+    pub broadcast_id: String,
+    pub subscription_id: String,
+    pub email: String,
+}
+
+/// A step of the drip sequence of a campaign to send to one subscriber.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DripEmail {
+    pub campaign_id: String,
+    pub subscription_id: String,
+    pub email: String,
+    /// The index of the step in the sequence
+    pub step: usize,
+}
+
+/// A message in the email queue.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EmailJob {
+    Confirmation(SubscribeEventPayload),
+    /// The confirmation email sent again, with a fresh token
+    Reminder(SubscribeEventPayload),
+    Broadcast(BroadcastEmail),
+    Drip(DripEmail),
+}
+
+impl EmailJob {
+    /// Parses a message, which is a confirmation when it has no `kind` (as
+    /// queued before there were other kinds of emails).
+    pub fn from_json(message: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(message)?;
+        Ok(match value.get("kind") {
+            Some(_) => serde_json::from_value(value)?,
+            None => EmailJob::Confirmation(serde_json::from_value(value)?),
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -14,26 +76,58 @@ pub struct SubscribeConfirmationTokenClaims {
     pub subscription_id: String,
     pub campaign_id: String,
     pub email: String,
+    /// The locale the subscriber chose, still known once the subscription
+    /// has expired
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
     pub nbf: u64,
     pub iat: u64,
     pub exp: u64,
 }
 
+/// The current unix timestamp, in seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// The claims of the token in the unsubscribe links.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnsubscribeTokenClaims {
+    pub subscription_id: String,
+    pub campaign_id: String,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+impl UnsubscribeTokenClaims {
+    pub fn new(subscription_id: String, campaign_id: String, expire_in_seconds: u64) -> Self {
+        let now = unix_now();
+        Self {
+            subscription_id,
+            campaign_id,
+            iat: now,
+            exp: now + expire_in_seconds,
+        }
+    }
+}
+
 impl SubscribeConfirmationTokenClaims {
     pub fn new(
         subscription_id: String,
         campaign_id: String,
         email: String,
+        locale: Option<String>,
         expire_in_seconds: u64,
     ) -> Self {
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let now = unix_now();
         Self {
             subscription_id,
             campaign_id,
             email,
+            locale,
             nbf: now,
             iat: now,
             exp: now + expire_in_seconds,
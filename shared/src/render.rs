@@ -0,0 +1,45 @@
+/// Renders a template, replacing the `{{ name }}` placeholders with the
+/// HTML-escaped values of the variables. Unknown placeholders are left as
+/// they are.
+pub fn render(template: &str, variables: &[(&str, &str)]) -> String {
+    render_with(template, variables, escape_html)
+}
+
+/// Renders a plain text template, with the values as they are.
+pub fn render_text(template: &str, variables: &[(&str, &str)]) -> String {
+    render_with(template, variables, str::to_string)
+}
+
+fn render_with(template: &str, variables: &[(&str, &str)], escape: fn(&str) -> String) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        let name = rest[start + 2..start + end].trim();
+        output.push_str(&rest[..start]);
+        match variables.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => output.push_str(&escape(value)),
+            None => output.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    output.push_str(rest);
+    output
+}
+
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
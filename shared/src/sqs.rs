@@ -0,0 +1,64 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use aws_sdk_sqs::types::SendMessageBatchRequestEntry;
+
+// the limit of SendMessageBatch
+pub const SEND_MESSAGE_BATCH_SIZE: usize = 10;
+
+/// How many times a batch call is made before giving up on the items it
+/// keeps failing.
+pub const MAX_ATTEMPTS: u32 = 8;
+
+/// How long to wait before the next attempt of a batch call.
+pub fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(50 * 2u64.pow(attempt))
+}
+
+/// Makes a call until it succeeds, up to `attempts` times, returning the
+/// error of the last attempt.
+pub async fn retry<T, E, F, Fut>(attempts: u32, mut call: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        match call().await {
+            Err(_) if attempt + 1 < attempts => {
+                tokio::time::sleep(backoff(attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Sends a batch of messages to a queue, retrying the failed entries.
+pub async fn send_batch(
+    client: &aws_sdk_sqs::Client,
+    queue_url: &str,
+    mut entries: Vec<SendMessageBatchRequestEntry>,
+) -> Result<()> {
+    for attempt in 0..MAX_ATTEMPTS {
+        let output = client
+            .send_message_batch()
+            .queue_url(queue_url)
+            .set_entries(Some(entries.clone()))
+            .send()
+            .await?;
+        let failed: HashSet<String> = output.failed.into_iter().map(|f| f.id).collect();
+        entries.retain(|entry| failed.contains(&entry.id));
+        if entries.is_empty() {
+            return Ok(());
+        }
+        tokio::time::sleep(backoff(attempt)).await;
+    }
+    Err(anyhow!(
+        "{} messages still not queued after {} attempts",
+        entries.len(),
+        MAX_ATTEMPTS
+    ))
+}
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fields::FieldValue;
+
+/// A subscription to a campaign, as stored in the subscriptions table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub campaign_id: String,
+    pub subscription_id: String,
+    pub email: String,
+    #[serde(default)]
+    pub ip: String,
+    /// The locale of the campaign the subscriber chose
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// The values of the extra fields of the form, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, FieldValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// When the last confirmation email (or reminder) was sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<u64>,
+    /// The SES message id of the last confirmation email
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ses_message_id: Option<String>,
+    /// How many confirmation emails (reminders included) were sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_attempts: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opened_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmed_at: Option<u64>,
+    /// When SES last reported an email as delivered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivered_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unsubscribed_at: Option<u64>,
+    /// When sending to the address got suppressed (bounces, complaints)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suppressed_at: Option<u64>,
+    /// How many confirmation reminders were sent, and when the last one was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminders_sent: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminded_at: Option<u64>,
+    /// When the last confirmation email (or reminder) was sent, or the
+    /// subscription created until the first one is, only while the
+    /// subscription is waiting for a reminder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_sent_at: Option<u64>,
+    /// When DynamoDB deletes a subscription that was never confirmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// How many emails of the drip sequence were sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drip_steps_sent: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub import: Option<ImportProvenance>,
+}
+
+/// Where an imported subscription comes from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportProvenance {
+    pub source: Option<String>,
+    pub s3_key: String,
+    pub imported_at: u64,
+    /// Whether the subscription was imported as already confirmed
+    pub pre_confirmed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    Pending,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl Subscription {
+    pub fn status(&self) -> SubscriptionStatus {
+        match (self.confirmed_at, self.unsubscribed_at) {
+            (_, Some(_)) => SubscriptionStatus::Unsubscribed,
+            (Some(_), None) => SubscriptionStatus::Confirmed,
+            (None, None) => SubscriptionStatus::Pending,
+        }
+    }
+
+    /// Confirmed, still subscribed and not suppressed.
+    pub fn receives_broadcasts(&self) -> bool {
+        self.status() == SubscriptionStatus::Confirmed && self.suppressed_at.is_none()
+    }
+}
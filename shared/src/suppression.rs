@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// An address nothing is sent to anymore, as stored in the suppressions
+/// table (keyed by the normalized address).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Suppression {
+    pub email: String,
+    pub reason: SuppressionReason,
+    /// The bounce subtype or the complaint feedback type reported by SES
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// The SES message that bounced or was complained about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ses_message_id: Option<String>,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    Bounce,
+    Complaint,
+}
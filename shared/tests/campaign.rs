@@ -0,0 +1,177 @@
+use shared::campaign::{Campaign, CampaignStatus, DripStep};
+use shared::fields::{FieldKind, FormField};
+
+fn valid() -> Campaign {
+    Campaign {
+        campaign_id: "test".to_string(),
+        name: "test campaign".to_string(),
+        reward_s3_key: "campaigns/test/reward/reward.pdf".to_string(),
+        email_template_s3_key: String::new(),
+        thank_you_message: "Thanks!".to_string(),
+        sender_email: None,
+        from_name: None,
+        reply_to: None,
+        locale: None,
+        translations: Default::default(),
+        fields: vec![],
+        drip: vec![],
+        status: CampaignStatus::Active,
+        created_at: None,
+        updated_at: None,
+    }
+}
+
+fn errors(campaign: &Campaign) -> Vec<String> {
+    campaign
+        .validate()
+        .expect_err("the campaign should be invalid")
+        .0
+}
+
+fn drip_step(after: &str) -> DripStep {
+    DripStep {
+        after: after.to_string(),
+        subject: "Hello".to_string(),
+        template_s3_key: "campaigns/test/drip/hello.html".to_string(),
+    }
+}
+
+#[test]
+fn valid_campaign() {
+    let campaign = Campaign {
+        sender_email: Some("news@example.com".to_string()),
+        from_name: Some("Élodie from Example".to_string()),
+        reply_to: Some("support@example.com".to_string()),
+        locale: Some("pt-BR".to_string()),
+        drip: vec![drip_step("1d"), drip_step("1d"), drip_step("72h")],
+        ..valid()
+    };
+    assert!(campaign.validate().is_ok());
+}
+
+#[test]
+fn invalid_campaign_id() {
+    let campaign = Campaign {
+        campaign_id: "Test/1".to_string(),
+        ..valid()
+    };
+    assert_eq!(
+        errors(&campaign),
+        ["campaign_id can only contain lowercase letters, digits, '-' and '_'"]
+    );
+
+    let campaign = Campaign {
+        campaign_id: "a".repeat(65),
+        ..valid()
+    };
+    assert_eq!(
+        errors(&campaign),
+        ["campaign_id should be between 1 and 64 characters"]
+    );
+}
+
+#[test]
+fn empty_texts() {
+    let campaign = Campaign {
+        name: " ".to_string(),
+        thank_you_message: String::new(),
+        ..valid()
+    };
+    assert_eq!(
+        errors(&campaign),
+        [
+            "name should be between 1 and 256 characters",
+            "thank_you_message can't be empty"
+        ]
+    );
+}
+
+#[test]
+fn invalid_sender() {
+    let campaign = Campaign {
+        sender_email: Some("news".to_string()),
+        from_name: Some("News\r\nBcc: someone@example.com".to_string()),
+        reply_to: Some("support@".to_string()),
+        ..valid()
+    };
+    assert_eq!(
+        errors(&campaign),
+        [
+            "from_name should be between 1 and 64 characters, on one line",
+            "sender_email should be an email address",
+            "reply_to should be an email address"
+        ]
+    );
+}
+
+#[test]
+fn invalid_locales() {
+    let campaign = Campaign {
+        locale: Some("english".to_string()),
+        translations: [("fr_FR".to_string(), Default::default())].into(),
+        ..valid()
+    };
+    assert_eq!(
+        errors(&campaign),
+        [
+            "\"english\" is not a valid locale, expected e.g. en or pt-BR",
+            "\"fr_FR\" is not a valid locale, expected e.g. en or pt-BR"
+        ]
+    );
+}
+
+#[test]
+fn absolute_s3_keys() {
+    let campaign = Campaign {
+        reward_s3_key: "/reward.pdf".to_string(),
+        email_template_s3_key: "campaigns/../secret.html".to_string(),
+        ..valid()
+    };
+    assert_eq!(
+        errors(&campaign),
+        [
+            "reward_s3_key should be a relative S3 key",
+            "email_template_s3_key should be a relative S3 key"
+        ]
+    );
+}
+
+#[test]
+fn invalid_fields() {
+    let campaign = Campaign {
+        fields: vec![FormField {
+            name: "email".to_string(),
+            label: "Email".to_string(),
+            kind: FieldKind::Text,
+            required: false,
+        }],
+        ..valid()
+    };
+    assert_eq!(errors(&campaign), ["field 0 can't be named email"]);
+}
+
+#[test]
+fn invalid_drip() {
+    let campaign = Campaign {
+        drip: vec![
+            drip_step("3d"),
+            drip_step("1d"),
+            drip_step("soon"),
+            DripStep {
+                subject: String::new(),
+                template_s3_key: String::new(),
+                ..drip_step("7d")
+            },
+        ],
+        ..valid()
+    };
+    assert_eq!(
+        errors(&campaign),
+        [
+            "drip step 1 should come after the previous one",
+            "drip step 2 has an invalid delay \"soon\", expected e.g. 1d or 72h",
+            "drip step 3 has an empty subject",
+            "drip step 3 should have a relative template_s3_key"
+        ]
+    );
+}
@@ -0,0 +1,65 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use shared::email::{format_address, normalize_email};
+
+/// The encoded-words of a formatted address, decoded.
+fn decode_words(address: &str) -> Vec<String> {
+    let (words, _) = address.rsplit_once(" <").expect("an address with a name");
+    words
+        .split(' ')
+        .map(|word| {
+            assert!(word.len() <= 75, "{word} is longer than 75 characters");
+            let encoded = word
+                .strip_prefix("=?UTF-8?B?")
+                .and_then(|word| word.strip_suffix("?="))
+                .expect("an encoded-word");
+            String::from_utf8(STANDARD.decode(encoded).unwrap())
+                .expect("whole characters in every word")
+        })
+        .collect()
+}
+
+#[test]
+fn ascii_names() {
+    assert_eq!(format_address(None, "news@example.com"), "news@example.com");
+    assert_eq!(
+        format_address(Some("Say \"hi\" \\o/"), "news@example.com"),
+        "\"Say \\\"hi\\\" \\\\o/\" <news@example.com>"
+    );
+}
+
+#[test]
+fn non_ascii_names() {
+    assert_eq!(
+        format_address(Some("Élodie"), "news@example.com"),
+        "=?UTF-8?B?w4lsb2RpZQ==?= <news@example.com>"
+    );
+
+    // 45 bytes fit in a single word
+    let name = "é".repeat(22) + "e";
+    assert_eq!(decode_words(&format_address(Some(&name), "a@b.c")), [name]);
+}
+
+#[test]
+fn long_names() {
+    let name = "Élodie de l'équipe produit, pour la lettre d'information mensuelle";
+    let words = decode_words(&format_address(Some(name), "news@example.com"));
+    assert_eq!(words.len(), 2);
+    assert_eq!(words.concat(), name);
+
+    // without splitting the characters
+    let name = "日本語のニュースレター".repeat(6);
+    let words = decode_words(&format_address(Some(&name), "news@example.com"));
+    assert_eq!(words.len(), 5);
+    assert!(words[..4].iter().all(|word| word.len() == 45));
+    assert_eq!(words.concat(), name);
+}
+
+#[test]
+fn normalized_emails() {
+    assert_eq!(
+        normalize_email(" Ada.Lovelace@Example.COM ").as_deref(),
+        Some("Ada.Lovelace@example.com")
+    );
+    assert_eq!(normalize_email("ada@"), None);
+    assert_eq!(normalize_email("ada (comment)@example.com"), None);
+}
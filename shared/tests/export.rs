@@ -0,0 +1,136 @@
+use aws_sdk_dynamodb::types::AttributeValue;
+use shared::export::{ExportFilter, ExportFormat, ExportWriter};
+use shared::subscription::{Subscription, SubscriptionStatus};
+
+fn subscription(email: &str, created_at: u64) -> Subscription {
+    serde_json::from_value(serde_json::json!({
+        "campaign_id": "test",
+        "subscription_id": email,
+        "email": email,
+        "created_at": created_at,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn no_filter_expression_without_filters() {
+    let filter = ExportFilter {
+        status: None,
+        from: None,
+        to: None,
+    };
+    assert!(filter.filter_expression().is_none());
+}
+
+#[test]
+fn filter_expression_by_status() {
+    let expression = |status| {
+        ExportFilter {
+            status: Some(status),
+            from: None,
+            to: None,
+        }
+        .filter_expression()
+        .unwrap()
+    };
+    assert_eq!(
+        expression(SubscriptionStatus::Pending),
+        (
+            "attribute_not_exists(confirmed_at) AND attribute_not_exists(unsubscribed_at)"
+                .to_string(),
+            vec![]
+        )
+    );
+    assert_eq!(
+        expression(SubscriptionStatus::Confirmed).0,
+        "attribute_exists(confirmed_at) AND attribute_not_exists(unsubscribed_at)"
+    );
+    assert_eq!(
+        expression(SubscriptionStatus::Unsubscribed).0,
+        "attribute_exists(unsubscribed_at)"
+    );
+}
+
+#[test]
+fn filter_expression_by_date() {
+    let filter = ExportFilter {
+        status: Some(SubscriptionStatus::Unsubscribed),
+        from: Some(1717200000),
+        to: Some(1719792000),
+    };
+    assert_eq!(
+        filter.filter_expression().unwrap(),
+        (
+            "attribute_exists(unsubscribed_at) AND created_at >= :from AND created_at < :to"
+                .to_string(),
+            vec![
+                (":from", AttributeValue::N("1717200000".to_string())),
+                (":to", AttributeValue::N("1719792000".to_string())),
+            ]
+        )
+    );
+}
+
+#[test]
+fn csv_header_written_once_across_pages() {
+    let mut writer = ExportWriter::new(ExportFormat::Csv, vec![]);
+    assert_eq!(writer.write(vec![]).unwrap(), 0);
+    assert!(writer.output().is_empty());
+    assert_eq!(
+        writer
+            .write(vec![subscription("a@example.com", 1)])
+            .unwrap(),
+        1
+    );
+    let first_page = writer.output().len();
+    assert_eq!(
+        writer
+            .write(vec![
+                subscription("b@example.com", 2),
+                subscription("c@example.com", 3),
+            ])
+            .unwrap(),
+        2
+    );
+    let content = String::from_utf8(writer.output().clone()).unwrap();
+    let lines: Vec<_> = content.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("subscription_id,"), "{}", lines[0]);
+    assert!(lines[1].starts_with("a@example.com"));
+    assert!(lines[3].starts_with("c@example.com"));
+    assert!(first_page < content.len());
+}
+
+#[test]
+fn csv_pages_can_be_drained() {
+    let mut writer = ExportWriter::new(ExportFormat::Csv, vec![]);
+    writer
+        .write(vec![subscription("a@example.com", 1)])
+        .unwrap();
+    let first = std::mem::take(writer.output());
+    writer
+        .write(vec![subscription("b@example.com", 2)])
+        .unwrap();
+    let second = String::from_utf8(std::mem::take(writer.output())).unwrap();
+    assert_eq!(String::from_utf8(first).unwrap().lines().count(), 2);
+    assert_eq!(second.lines().count(), 1);
+    assert!(second.starts_with("b@example.com"));
+}
+
+#[test]
+fn jsonl_one_line_per_subscription() {
+    let mut writer = ExportWriter::new(ExportFormat::Jsonl, vec![]);
+    writer
+        .write(vec![subscription("a@example.com", 1)])
+        .unwrap();
+    writer
+        .write(vec![subscription("b@example.com", 2)])
+        .unwrap();
+    let content = String::from_utf8(writer.output().clone()).unwrap();
+    let lines: Vec<serde_json::Value> = content
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["email"], "b@example.com");
+}
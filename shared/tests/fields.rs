@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, HashMap};
+
+use shared::fields::{
+    field_variables, parse_fields, validate_fields, FieldKind, FieldValue, FormField,
+};
+
+fn field(name: &str, kind: FieldKind, required: bool) -> FormField {
+    FormField {
+        name: name.to_string(),
+        label: format!("Label of {name}"),
+        kind,
+        required,
+    }
+}
+
+fn values(values: &[(&str, &str)]) -> HashMap<String, String> {
+    values
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn parsed(values: &[(&str, FieldValue)]) -> BTreeMap<String, FieldValue> {
+    values
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect()
+}
+
+fn errors(fields: &[FormField]) -> Vec<String> {
+    let mut errors = vec![];
+    validate_fields(fields, &mut errors);
+    errors
+}
+
+#[test]
+fn valid_fields() {
+    let fields = [
+        field("first_name", FieldKind::Text, true),
+        field("age", FieldKind::Number, false),
+        field("consent", FieldKind::Checkbox, true),
+        FormField {
+            label: String::new(),
+            ..field("utm_source", FieldKind::Hidden, false)
+        },
+    ];
+    assert!(errors(&fields).is_empty());
+}
+
+#[test]
+fn invalid_fields() {
+    let fields = [
+        field("First Name", FieldKind::Text, false),
+        field(&"a".repeat(65), FieldKind::Text, false),
+        field("locale", FieldKind::Text, false),
+        field("age", FieldKind::Number, false),
+        field("age", FieldKind::Number, false),
+        FormField {
+            label: " ".to_string(),
+            ..field("city", FieldKind::Text, false)
+        },
+        field("referer", FieldKind::Hidden, true),
+    ];
+    assert_eq!(
+        errors(&fields),
+        [
+            "field 0 should have a name of 1 to 64 lowercase letters, digits and '_'",
+            "field 1 should have a name of 1 to 64 lowercase letters, digits and '_'",
+            "field 2 can't be named locale",
+            "field 4 is a duplicate of age",
+            "field 5 should have a label",
+            "field 6 can't be both hidden and required",
+        ]
+    );
+}
+
+#[test]
+fn checkboxes() {
+    let fields = [
+        field("consent", FieldKind::Checkbox, true),
+        field("newsletter", FieldKind::Checkbox, false),
+    ];
+    // browsers only send the checked boxes, whatever their value
+    assert_eq!(
+        parse_fields(&fields, &values(&[("consent", "on")])),
+        Ok(parsed(&[
+            ("consent", FieldValue::Bool(true)),
+            ("newsletter", FieldValue::Bool(false)),
+        ]))
+    );
+    assert_eq!(
+        parse_fields(&fields, &values(&[("consent", " "), ("newsletter", "on")])),
+        Err(vec!["Label of consent".to_string()])
+    );
+}
+
+#[test]
+fn numbers() {
+    let fields = [field("age", FieldKind::Number, false)];
+    assert_eq!(
+        parse_fields(&fields, &values(&[("age", " 42 ")])),
+        Ok(parsed(&[("age", FieldValue::Number(42.0))]))
+    );
+    assert_eq!(
+        parse_fields(&fields, &values(&[("age", "-1.5")])),
+        Ok(parsed(&[("age", FieldValue::Number(-1.5))]))
+    );
+    for invalid in ["forty", "NaN", "inf", "1e400"] {
+        assert_eq!(
+            parse_fields(&fields, &values(&[("age", invalid)])),
+            Err(vec!["Label of age".to_string()]),
+            "{invalid} should be invalid"
+        );
+    }
+}
+
+#[test]
+fn lengths() {
+    let fields = [FormField {
+        label: String::new(),
+        ..field("city", FieldKind::Text, false)
+    }];
+    // counted in characters, not bytes
+    let longest = "é".repeat(256);
+    assert_eq!(
+        parse_fields(&fields, &values(&[("city", &longest)])),
+        Ok(parsed(&[("city", FieldValue::Text(longest.clone()))]))
+    );
+    // without a label, the name identifies the field
+    assert_eq!(
+        parse_fields(&fields, &values(&[("city", &format!("{longest}e"))])),
+        Err(vec!["city".to_string()])
+    );
+}
+
+#[test]
+fn missing_values() {
+    let fields = [
+        field("first_name", FieldKind::Text, true),
+        field("company", FieldKind::Text, false),
+        field("utm_source", FieldKind::Hidden, false),
+    ];
+    assert_eq!(
+        parse_fields(
+            &fields,
+            &values(&[("first_name", "  "), ("company", ""), ("unknown", "value")])
+        ),
+        Err(vec!["Label of first_name".to_string()])
+    );
+    assert_eq!(
+        parse_fields(
+            &fields,
+            &values(&[("first_name", " Ada "), ("utm_source", "newsletter")])
+        ),
+        Ok(parsed(&[
+            ("first_name", FieldValue::Text("Ada".to_string())),
+            ("utm_source", FieldValue::Text("newsletter".to_string())),
+        ]))
+    );
+}
+
+#[test]
+fn template_variables() {
+    let fields = [
+        field("first_name", FieldKind::Text, true),
+        field("age", FieldKind::Number, false),
+        field("consent", FieldKind::Checkbox, true),
+        field("company", FieldKind::Text, false),
+    ];
+    let values = parsed(&[
+        ("first_name", FieldValue::Text("Ada".to_string())),
+        ("age", FieldValue::Number(36.0)),
+        ("consent", FieldValue::Bool(true)),
+        // no longer a field of the campaign
+        ("city", FieldValue::Text("London".to_string())),
+    ]);
+    assert_eq!(
+        field_variables(&fields, &values),
+        [
+            ("fields.first_name".to_string(), "Ada".to_string()),
+            ("fields.age".to_string(), "36".to_string()),
+            ("fields.consent".to_string(), "true".to_string()),
+            ("fields.company".to_string(), String::new()),
+        ]
+    );
+}
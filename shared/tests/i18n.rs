@@ -0,0 +1,103 @@
+use shared::campaign::{Campaign, CampaignStatus};
+use shared::i18n::Text;
+
+/// A campaign in English, translated to French, Brazilian Portuguese and
+/// German.
+fn campaign() -> Campaign {
+    Campaign {
+        campaign_id: "test".to_string(),
+        name: "test campaign".to_string(),
+        reward_s3_key: "campaigns/test/reward/reward.pdf".to_string(),
+        email_template_s3_key: String::new(),
+        thank_you_message: "Thanks!".to_string(),
+        sender_email: None,
+        from_name: None,
+        reply_to: None,
+        locale: Some("en".to_string()),
+        translations: ["fr", "pt-BR", "de"]
+            .into_iter()
+            .map(|locale| {
+                let texts = [(Text::ThankYouMessage, format!("thanks in {locale}"))];
+                (locale.to_string(), texts.into())
+            })
+            .collect(),
+        fields: vec![],
+        drip: vec![],
+        status: CampaignStatus::Active,
+        created_at: None,
+        updated_at: None,
+    }
+}
+
+fn negotiate(requested: Option<&str>, accept_language: Option<&str>) -> String {
+    campaign().negotiate_locale(requested, accept_language)
+}
+
+#[test]
+fn requested_locale() {
+    assert_eq!(negotiate(Some("pt-BR"), Some("fr")), "pt-BR");
+    // matched by language, ignoring the case
+    assert_eq!(negotiate(Some("FR-ch"), None), "fr");
+    assert_eq!(negotiate(Some("pt-br"), None), "pt-BR");
+    assert_eq!(negotiate(Some("pt-PT"), None), "pt-BR");
+    // unless the campaign doesn't have it (anymore)
+    assert_eq!(negotiate(Some("it"), Some("de")), "de");
+}
+
+#[test]
+fn quality_values() {
+    assert_eq!(negotiate(None, Some("fr;q=0.5, de;q=0.8")), "de");
+    assert_eq!(negotiate(None, Some("de;q=0.5,fr")), "fr");
+    // the order of the header breaks the ties
+    assert_eq!(negotiate(None, Some("de;q=0.8, fr;q=0.8")), "de");
+    // with spaces and other parameters
+    assert_eq!(
+        negotiate(None, Some("de ; level=1 ; q=0.4 , fr ; q=0.3")),
+        "de"
+    );
+    // invalid qualities count as 1
+    assert_eq!(negotiate(None, Some("de;q=0.9, fr;q=high")), "fr");
+}
+
+#[test]
+fn unavailable_ranges() {
+    // the first range the campaign has
+    assert_eq!(
+        negotiate(None, Some("it-IT, it;q=0.9, pt;q=0.8, en;q=0.7")),
+        "pt-BR"
+    );
+    // refused ones are skipped
+    assert_eq!(negotiate(None, Some("fr;q=0, de;q=0.1")), "de");
+}
+
+#[test]
+fn default_locale() {
+    assert_eq!(negotiate(None, None), "en");
+    assert_eq!(negotiate(None, Some("")), "en");
+    assert_eq!(negotiate(None, Some("*")), "en");
+    assert_eq!(negotiate(None, Some("it, ja;q=0.5")), "en");
+    assert_eq!(negotiate(None, Some("fr;q=0")), "en");
+    assert_eq!(negotiate(None, Some(",;q=1,")), "en");
+
+    let campaign = Campaign {
+        locale: None,
+        ..campaign()
+    };
+    assert_eq!(campaign.default_locale(), "en");
+    assert_eq!(campaign.negotiate_locale(None, Some("es")), "en");
+}
+
+#[test]
+fn texts() {
+    let campaign = campaign();
+    assert_eq!(campaign.text("de", Text::ThankYouMessage), "thanks in de");
+    // falling back to the default locale, then to the campaign itself
+    assert_eq!(campaign.text("it", Text::ThankYouMessage), "Thanks!");
+    assert_eq!(
+        campaign.text("de", Text::InvalidToken),
+        Text::InvalidToken.default_text()
+    );
+    // named in snake case in the translations
+    let text: Text = serde_json::from_str("\"unsubscribed\"").unwrap();
+    assert_eq!(text, Text::Unsubscribed);
+}
@@ -0,0 +1,177 @@
+use jsonwebtoken::{decode_header, encode, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use shared::keyring::Keyring;
+
+// RFC 8032 test vector 1, as a PKCS#8 v1 document and the JWK `x`
+const ED25519_PRIVATE_KEY: &str =
+    "MC4CAQAwBQYDK2VwBCIEIJ1hsZ3v/VpguoRK9JLsLMREScVpezJpGXA7rAMcrn9g";
+const ED25519_PUBLIC_KEY: &str = "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo";
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: u64,
+}
+
+fn claims() -> Claims {
+    Claims {
+        sub: "subscription".to_string(),
+        exp: shared::unix_now() + 60,
+    }
+}
+
+fn hs256_key(kid: &str, secret: &str) -> String {
+    format!(r#"{{ "kid": "{kid}", "alg": "HS256", "secret": "{secret}" }}"#)
+}
+
+fn eddsa_key(kid: &str, private_key: Option<&str>) -> String {
+    match private_key {
+        Some(private_key) => format!(
+            r#"{{ "kid": "{kid}", "alg": "EdDSA", "private_key": "{private_key}", "public_key": "{ED25519_PUBLIC_KEY}" }}"#
+        ),
+        None => {
+            format!(r#"{{ "kid": "{kid}", "alg": "EdDSA", "public_key": "{ED25519_PUBLIC_KEY}" }}"#)
+        }
+    }
+}
+
+fn load(active_kid: &str, keys: &[String]) -> anyhow::Result<Keyring> {
+    Keyring::from_json(&format!(
+        r#"{{ "active_kid": "{active_kid}", "keys": [{}] }}"#,
+        keys.join(", ")
+    ))
+}
+
+fn kid(token: &str) -> Option<String> {
+    decode_header(token).expect("a valid token").kid
+}
+
+#[test]
+fn hs256_roundtrip() {
+    let keyring = load("2024-06", &[hs256_key("2024-06", "secret")]).unwrap();
+    let token = keyring.encode(&claims()).unwrap();
+    assert_eq!(kid(&token).as_deref(), Some("2024-06"));
+    assert_eq!(keyring.decode::<Claims>(&token).unwrap(), claims());
+}
+
+#[test]
+fn eddsa_roundtrip() {
+    let keyring = load(
+        "2024-06",
+        &[eddsa_key("2024-06", Some(ED25519_PRIVATE_KEY))],
+    )
+    .unwrap();
+    let token = keyring.encode(&claims()).unwrap();
+    assert_eq!(keyring.decode::<Claims>(&token).unwrap(), claims());
+
+    // the public key alone verifies the tokens
+    let verifier = load(
+        "2024-07",
+        &[hs256_key("2024-07", "secret"), eddsa_key("2024-06", None)],
+    )
+    .unwrap();
+    assert_eq!(verifier.decode::<Claims>(&token).unwrap(), claims());
+}
+
+#[test]
+fn rotation() {
+    let old = load("2024-01", &[hs256_key("2024-01", "old secret")]).unwrap();
+    let old_token = old.encode(&claims()).unwrap();
+
+    // the new key signs, the old one still verifies
+    let rotated = load(
+        "2024-06",
+        &[
+            hs256_key("2024-06", "new secret"),
+            hs256_key("2024-01", "old secret"),
+        ],
+    )
+    .unwrap();
+    let new_token = rotated.encode(&claims()).unwrap();
+    assert_eq!(kid(&new_token).as_deref(), Some("2024-06"));
+    assert_eq!(rotated.decode::<Claims>(&old_token).unwrap(), claims());
+    assert_eq!(rotated.decode::<Claims>(&new_token).unwrap(), claims());
+
+    // until it is removed
+    let retired = load("2024-06", &[hs256_key("2024-06", "new secret")]).unwrap();
+    let err = retired.decode::<Claims>(&old_token).unwrap_err();
+    assert_eq!(err.to_string(), "Unknown kid: 2024-01");
+    assert!(old.decode::<Claims>(&new_token).is_err());
+}
+
+#[test]
+fn tokens_without_kid() {
+    let legacy = |secret: &str| {
+        encode(
+            &Header::default(),
+            &claims(),
+            &EncodingKey::from_secret(secret.as_ref()),
+        )
+        .unwrap()
+    };
+    let keyring = load(
+        "2024-06",
+        &[
+            hs256_key("2024-06", "new secret"),
+            hs256_key("2024-01", "legacy secret"),
+            eddsa_key("2023-12", None),
+        ],
+    )
+    .unwrap();
+
+    // any key with the same algorithm is tried
+    assert_eq!(
+        keyring.decode::<Claims>(&legacy("legacy secret")).unwrap(),
+        claims()
+    );
+    assert!(keyring.decode::<Claims>(&legacy("another secret")).is_err());
+
+    let eddsa_only = load(
+        "2024-06",
+        &[eddsa_key("2024-06", Some(ED25519_PRIVATE_KEY))],
+    )
+    .unwrap();
+    let err = eddsa_only
+        .decode::<Claims>(&legacy("legacy secret"))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "No key available for algorithm HS256");
+}
+
+#[test]
+fn invalid_keyrings() {
+    let error = |active_kid: &str, keys: &[String]| {
+        load(active_kid, keys)
+            .err()
+            .expect("the keyring should be invalid")
+            .to_string()
+    };
+
+    assert_eq!(
+        error("2024-06", &[hs256_key("2024-01", "secret")]),
+        "Active kid 2024-06 not found in keyring"
+    );
+    // encode relies on the active key being able to sign
+    assert_eq!(
+        error("2024-06", &[eddsa_key("2024-06", None)]),
+        "Active kid 2024-06 has no private key"
+    );
+    assert_eq!(
+        error(
+            "2024-06",
+            &[
+                hs256_key("2024-06", "secret"),
+                hs256_key("2024-06", "other")
+            ]
+        ),
+        "Duplicate kid in keyring: 2024-06"
+    );
+    assert!(
+        error("2024-06", &[eddsa_key("2024-06", Some("not base64!"))])
+            .starts_with("Invalid private key for kid 2024-06")
+    );
+    assert!(Keyring::from_json("{}")
+        .err()
+        .expect("the keyring should be invalid")
+        .to_string()
+        .starts_with("Failed to parse keyring document"));
+}
@@ -0,0 +1,46 @@
+use std::cell::Cell;
+
+use shared::sqs::retry;
+
+#[tokio::test]
+async fn retry_until_success() {
+    let calls = Cell::new(0);
+    let result: Result<u32, String> = retry(3, || {
+        calls.set(calls.get() + 1);
+        let call = calls.get();
+        async move {
+            match call {
+                3 => Ok(call),
+                _ => Err(format!("attempt {} failed", call)),
+            }
+        }
+    })
+    .await;
+    assert_eq!(result, Ok(3));
+    assert_eq!(calls.get(), 3);
+}
+
+#[tokio::test]
+async fn retry_gives_up_with_last_error() {
+    let calls = Cell::new(0);
+    let result: Result<(), String> = retry(3, || {
+        calls.set(calls.get() + 1);
+        let call = calls.get();
+        async move { Err(format!("attempt {} failed", call)) }
+    })
+    .await;
+    assert_eq!(result, Err("attempt 3 failed".to_string()));
+    assert_eq!(calls.get(), 3);
+}
+
+#[tokio::test]
+async fn no_retry_after_success() {
+    let calls = Cell::new(0);
+    let result: Result<(), String> = retry(3, || {
+        calls.set(calls.get() + 1);
+        async { Ok(()) }
+    })
+    .await;
+    assert_eq!(result, Ok(()));
+    assert_eq!(calls.get(), 1);
+}